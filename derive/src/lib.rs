@@ -0,0 +1,296 @@
+//! Derive macros for dabbot-cache's cached models.
+//!
+//! `#[derive(RespHash)]` implements `RespHash` and `FromResp` for a struct
+//! with named fields, so that a model's hash fields are named and encoded in
+//! one place for both reads and writes. Structs without `#[resp(json)]`
+//! fields, whose encoding can't fail, also get `ToHashChanges`.
+//!
+//! The generated code refers to items by their `crate::` paths, so the derive
+//! is only usable from within dabbot-cache itself.
+//!
+//! # Attributes
+//!
+//! On the struct:
+//!
+//! - `#[resp(default)]`: fields missing from the hash take their values from
+//!   the struct's `Default` implementation.
+//!
+//! On fields:
+//!
+//! - `#[resp(rename = "name")]`: use a different hash field name.
+//! - `#[resp(default)]`: use the field type's default when it's missing.
+//! - `#[resp(delete_none)]`: on an `Option`, delete the hash field when the
+//!   value is `None` instead of leaving it untouched.
+//! - `#[resp(json)]`: store the value as JSON.
+//! - `#[resp(skip_encoding)]`: read the field but never write it, for values
+//!   like ID Sets that are stored under their own keys and joined in on read.
+//!
+//! `Option` fields are `None` when missing from the hash.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input,
+    Attribute,
+    Data,
+    DeriveInput,
+    Error,
+    Fields,
+    Lit,
+    Meta,
+    NestedMeta,
+    Result,
+    Type,
+};
+
+#[proc_macro_derive(RespHash, attributes(resp))]
+pub fn derive_resp_hash(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand(input).unwrap_or_else(|why| why.to_compile_error()).into()
+}
+
+#[derive(Default)]
+struct ContainerOptions {
+    default: bool,
+}
+
+#[derive(Default)]
+struct FieldOptions {
+    default: bool,
+    delete_none: bool,
+    json: bool,
+    rename: Option<String>,
+    skip_encoding: bool,
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let container = container_options(&input.attrs)?;
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(Error::new_spanned(
+                &input,
+                "RespHash can only be derived for structs with named fields",
+            )),
+        },
+        _ => return Err(Error::new_spanned(
+            &input,
+            "RespHash can only be derived for structs",
+        )),
+    };
+
+    let mut decoders = Vec::with_capacity(fields.len());
+    let mut encoders = Vec::with_capacity(fields.len());
+    let mut fallible = false;
+
+    for field in fields {
+        let ident = field.ident.as_ref().expect("named field");
+        let options = field_options(&field.attrs)?;
+        let key = options.rename.clone().unwrap_or_else(|| ident.to_string());
+        let optional = is_option(&field.ty);
+
+        if options.delete_none && !optional {
+            return Err(Error::new_spanned(
+                field,
+                "`delete_none` can only be used on `Option` fields",
+            ));
+        }
+
+        let take = if options.json {
+            quote!(hash.take_json(#key)?)
+        } else {
+            quote!(hash.take(#key)?)
+        };
+
+        let decoder = if optional {
+            take
+        } else if options.default {
+            quote!(#take.unwrap_or_default())
+        } else if container.default {
+            quote!(#take.unwrap_or(default.#ident))
+        } else {
+            quote!(#take.ok_or_else(|| crate::hash::HashFields::missing(#key))?)
+        };
+
+        decoders.push(quote!(#ident: #decoder));
+
+        if options.skip_encoding {
+            continue;
+        }
+
+        fallible |= options.json;
+
+        let set = if options.json {
+            quote!(changes.set_json(#key, value)?)
+        } else {
+            quote!(changes.set(#key, value))
+        };
+
+        encoders.push(if optional {
+            let none = if options.delete_none {
+                quote!(changes.delete(#key))
+            } else {
+                quote!({})
+            };
+
+            quote! {
+                match &self.#ident {
+                    Some(value) => #set,
+                    None => #none,
+                }
+            }
+        } else {
+            quote! {
+                {
+                    let value = &self.#ident;
+                    #set;
+                }
+            }
+        });
+    }
+
+    let default = if container.default {
+        quote!(let default = <Self as ::std::default::Default>::default();)
+    } else {
+        quote!()
+    };
+
+    // Models that can't fail to encode get an infallible encoding, which
+    // their fallible one wraps.
+    let (encode, infallible) = if fallible {
+        let encode = quote! {
+            fn to_resp_hash(&self) -> crate::Result<crate::hash::HashChanges> {
+                let mut changes = crate::hash::HashChanges::default();
+                #(#encoders)*
+
+                Ok(changes)
+            }
+        };
+
+        (encode, quote!())
+    } else {
+        let encode = quote! {
+            fn to_resp_hash(&self) -> crate::Result<crate::hash::HashChanges> {
+                Ok(<Self as crate::hash::ToHashChanges>::to_hash_changes(self))
+            }
+        };
+        let infallible = quote! {
+            impl crate::hash::ToHashChanges for #name {
+                fn to_hash_changes(&self) -> crate::hash::HashChanges {
+                    let mut changes = crate::hash::HashChanges::default();
+                    #(#encoders)*
+
+                    changes
+                }
+            }
+        };
+
+        (encode, infallible)
+    };
+
+    Ok(quote! {
+        impl crate::hash::RespHash for #name {
+            fn from_resp_hash(
+                resp: ::redis_async::resp::RespValue,
+            ) -> ::std::result::Result<Self, ::redis_async::error::Error> {
+                let mut hash = crate::hash::HashFields::new(resp)?;
+                #default
+
+                Ok(Self {
+                    #(#decoders,)*
+                })
+            }
+
+            #encode
+        }
+
+        #infallible
+
+        impl ::redis_async::resp::FromResp for #name {
+            fn from_resp_int(
+                resp: ::redis_async::resp::RespValue,
+            ) -> ::std::result::Result<Self, ::redis_async::error::Error> {
+                <Self as crate::hash::RespHash>::from_resp_hash(resp)
+            }
+        }
+    })
+}
+
+fn container_options(attrs: &[Attribute]) -> Result<ContainerOptions> {
+    let mut options = ContainerOptions::default();
+
+    for meta in resp_metas(attrs)? {
+        match &meta {
+            NestedMeta::Meta(Meta::Word(word)) if word == "default" => {
+                options.default = true;
+            },
+            other => return Err(Error::new_spanned(other, "Unknown resp attribute")),
+        }
+    }
+
+    Ok(options)
+}
+
+fn field_options(attrs: &[Attribute]) -> Result<FieldOptions> {
+    let mut options = FieldOptions::default();
+
+    for meta in resp_metas(attrs)? {
+        match &meta {
+            NestedMeta::Meta(Meta::Word(word)) if word == "default" => {
+                options.default = true;
+            },
+            NestedMeta::Meta(Meta::Word(word)) if word == "delete_none" => {
+                options.delete_none = true;
+            },
+            NestedMeta::Meta(Meta::Word(word)) if word == "json" => {
+                options.json = true;
+            },
+            NestedMeta::Meta(Meta::Word(word)) if word == "skip_encoding" => {
+                options.skip_encoding = true;
+            },
+            NestedMeta::Meta(Meta::NameValue(pair)) if pair.ident == "rename" => {
+                match &pair.lit {
+                    Lit::Str(name) => options.rename = Some(name.value()),
+                    other => return Err(Error::new_spanned(other, "Expected a string")),
+                }
+            },
+            other => return Err(Error::new_spanned(other, "Unknown resp attribute")),
+        }
+    }
+
+    Ok(options)
+}
+
+/// Collects the nested items of all `#[resp(...)]` attributes.
+fn resp_metas(attrs: &[Attribute]) -> Result<Vec<NestedMeta>> {
+    let mut metas = vec![];
+
+    for attr in attrs {
+        if attr.path.segments.len() != 1 || attr.path.segments[0].ident != "resp" {
+            continue;
+        }
+
+        match attr.parse_meta()? {
+            Meta::List(list) => metas.extend(list.nested),
+            other => return Err(Error::new_spanned(other, "Expected #[resp(...)]")),
+        }
+    }
+
+    Ok(metas)
+}
+
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => match path.path.segments.last() {
+            Some(segment) => segment.value().ident == "Option",
+            None => false,
+        },
+        _ => false,
+    }
+}
@@ -0,0 +1,251 @@
+//! A synchronous facade over [`Cache`], for tools that aren't async.
+//!
+//! [`blocking::Cache`] owns a Tokio runtime that drives its Redis connection
+//! in the background, and runs each operation to completion on the calling
+//! thread. Its methods mirror those of [`Cache`], taking the same arguments
+//! and returning the same types and errors.
+//!
+//! Fire-and-forget writes, such as [`upsert_presence`], are sent by the
+//! runtime in the background, so they can still be in flight when a method
+//! returns. Call [`flush`] before dropping the cache if they must be written
+//! before the program exits.
+//!
+//! [`Cache`]: ../struct.Cache.html
+//! [`blocking::Cache`]: struct.Cache.html
+//! [`flush`]: struct.Cache.html#method.flush
+//! [`upsert_presence`]: struct.Cache.html#method.upsert_presence
+
+use crate::{
+    commands::CommandablePairedConnection,
+    config::Config,
+    error::Result,
+    model::{
+        Emoji as CachedEmoji,
+        Guild as CachedGuild,
+        GuildInconsistencies,
+        GuildParts,
+        GuildSettings,
+        GuildSettingsPatch,
+        HistoryEntry,
+        LavalinkNode,
+        LoopMode,
+        Member as CachedMember,
+        Message as CachedMessage,
+        Presence as CachedPresence,
+        Role as CachedRole,
+        SkipVotes,
+        User as CachedUser,
+        VoiceConnectionInfo,
+        VoiceState as CachedVoiceState,
+    },
+    lavalink::NodeAssignment,
+    ratelimit::RateLimit,
+};
+use futures::executor;
+use redis_async::client;
+use serenity::model::prelude::*;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    num::NonZeroU64,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::runtime::Runtime;
+
+/// A synchronous version of [`Cache`].
+///
+/// [`Cache`]: ../struct.Cache.html
+pub struct Cache {
+    inner: crate::Cache,
+    // Kept alive to drive the connection.
+    _runtime: Runtime,
+}
+
+impl Cache {
+    /// Connects to Redis at the given address.
+    pub fn connect(addr: &SocketAddr) -> Result<Self> {
+        Self::connect_with_config(addr, Config::default())
+    }
+
+    /// Connects to Redis at the given address with the given configuration.
+    pub fn connect_with_config(addr: &SocketAddr, config: Config) -> Result<Self> {
+        let mut runtime = Runtime::new()?;
+        let connection = runtime.block_on(client::paired_connect(addr))?;
+        let inner = crate::Cache::with_config(Arc::new(connection), config);
+        // Methods are run on the calling thread rather than in the runtime,
+        // so fire-and-forget writes wouldn't find it to wait on replies.
+        inner.inner().start_write_tracker(&mut runtime.executor())?;
+
+        Ok(Self {
+            inner,
+            _runtime: runtime,
+        })
+    }
+
+    /// Returns the async cache, for use with an executor of your own.
+    pub fn as_async(&self) -> &crate::Cache {
+        &self.inner
+    }
+
+    /// Returns the configuration of the cache.
+    pub fn config(&self) -> &Config {
+        self.inner.config()
+    }
+
+    /// Returns the inner commandable paired connection for use in lower level
+    /// data manipulation.
+    pub fn inner(&self) -> &CommandablePairedConnection {
+        self.inner.inner()
+    }
+
+    /// Iterates over the IDs of all cached guilds.
+    ///
+    /// Each batch of IDs is fetched when the previous one runs out.
+    pub fn iter_guild_ids(&self) -> impl Iterator<Item = Result<u64>> {
+        executor::block_on_stream(Box::pin(self.inner.iter_guild_ids()))
+    }
+
+    /// Iterates over the IDs of the members of a guild.
+    pub fn iter_guild_member_ids(
+        &self,
+        guild_id: u64,
+    ) -> impl Iterator<Item = Result<u64>> {
+        executor::block_on_stream(Box::pin(self.inner.iter_guild_member_ids(guild_id)))
+    }
+
+    pub fn get_channels<'a>(
+        &'a self,
+        ids: impl IntoIterator<Item = u64> + 'a,
+    ) -> Result<HashMap<u64, Channel>> {
+        executor::block_on(self.inner.get_channels(ids))
+    }
+
+    pub fn delete_channels<'a>(&'a self, ids: impl IntoIterator<Item = u64> + 'a) {
+        self.inner.delete_channels(ids)
+    }
+}
+
+/// Defines methods that wait on the async method of the same name, and
+/// methods that call through to a method that isn't async.
+macro_rules! blocking_methods {
+    (
+        async {
+            $(fn $name:ident(&self $(, $arg:ident: $ty:ty)*) -> $ret:ty;)*
+        }
+        sync {
+            $(fn $sync_name:ident(&self $(, $sync_arg:ident: $sync_ty:ty)*) $(-> $sync_ret:ty)*;)*
+        }
+    ) => {
+        impl Cache {
+            $(
+                pub fn $name(&self $(, $arg: $ty)*) -> $ret {
+                    executor::block_on(self.inner.$name($($arg),*))
+                }
+            )*
+
+            $(
+                pub fn $sync_name(&self $(, $sync_arg: $sync_ty)*) $(-> $sync_ret)* {
+                    self.inner.$sync_name($($sync_arg),*)
+                }
+            )*
+        }
+    };
+}
+
+blocking_methods! {
+    async {
+        fn add_skip_vote(&self, guild_id: u64, track: &str, user_id: u64) -> Result<Option<bool>>;
+        fn assign_lavalink_node(&self, guild_id: u64, region: Option<&str>) -> Result<Option<NodeAssignment>>;
+        fn channel_listener_count(&self, channel_id: u64) -> Result<u64>;
+        fn channel_listeners(&self, channel_id: u64) -> Result<Vec<u64>>;
+        fn delete_choices(&self, guild_id: u64) -> Result<()>;
+        fn delete_guild_lavalink_node(&self, guild_id: u64) -> Result<()>;
+        fn delete_history(&self, guild_id: u64) -> Result<()>;
+        fn delete_join(&self, guild_id: u64) -> Result<()>;
+        fn delete_message(&self, channel_id: u64, message_id: u64) -> Result<bool>;
+        fn delete_messages(&self, channel_id: u64, message_ids: Vec<u64>) -> Result<u64>;
+        fn delete_voice_state(&self, guild_id: u64, user_id: u64) -> Result<bool>;
+        fn delete_voice_states(&self, guild_id: u64) -> Result<u64>;
+        fn flush(&self) -> Result<()>;
+        fn get_channel_voice_states(&self, channel_id: u64) -> Result<Vec<u64>>;
+        fn get_choices(&self, guild_id: u64) -> Result<Vec<String>>;
+        fn get_choices_ranged(&self, guild_id: u64, min: i64, max: i64) -> Result<Vec<String>>;
+        fn get_current_track(&self, guild_id: u64) -> Result<Option<String>>;
+        fn get_guild(&self, id: u64) -> Result<CachedGuild>;
+        fn get_guild_emojis(&self, guild_id: u64) -> Result<HashMap<u64, CachedEmoji>>;
+        fn get_guild_lavalink_node(&self, guild_id: u64) -> Result<Option<String>>;
+        fn get_guild_members(&self, guild_id: u64) -> Result<HashMap<u64, CachedMember>>;
+        fn get_guild_presences(&self, guild_id: u64) -> Result<HashMap<u64, CachedPresence>>;
+        fn get_guild_settings(&self, guild_id: u64) -> Result<GuildSettings>;
+        fn get_guild_with(&self, id: u64, parts: GuildParts) -> Result<CachedGuild>;
+        fn get_history(&self, guild_id: u64, page: u64, per_page: u64) -> Result<Vec<HistoryEntry>>;
+        fn get_history_len(&self, guild_id: u64) -> Result<u64>;
+        fn get_join(&self, guild_id: u64) -> Result<String>;
+        fn get_lavalink_nodes(&self) -> Result<Vec<LavalinkNode>>;
+        fn get_loop_mode(&self, guild_id: u64) -> Result<Option<LoopMode>>;
+        fn get_member(&self, guild_id: u64, user_id: u64) -> Result<Option<CachedMember>>;
+        fn get_members(&self, guild_id: u64, user_ids: Vec<u64>) -> Result<HashMap<u64, CachedMember>>;
+        fn get_presence(&self, guild_id: u64, user_id: u64) -> Result<Option<CachedPresence>>;
+        fn get_queue(&self, guild_id: u64) -> Result<Vec<String>>;
+        fn get_roles(&self, guild_id: u64) -> Result<HashMap<u64, CachedRole>>;
+        fn get_user(&self, user_id: u64) -> Result<Option<CachedUser>>;
+        fn get_voice_connection(&self, guild_id: u64, user_id: u64) -> Result<Option<VoiceConnectionInfo>>;
+        fn get_voice_state(&self, guild_id: u64, user_id: u64) -> Result<Option<CachedVoiceState>>;
+        fn get_voice_state_list(&self, guild_id: u64) -> Result<Vec<u64>>;
+        fn get_voice_states(&self, guild_id: u64) -> Result<HashMap<u64, CachedVoiceState>>;
+        fn guilds_for_shard(&self, shard_id: u64) -> Result<Vec<u64>>;
+        fn index_guild_ids(&self) -> Result<u64>;
+        fn patch_guild_settings(&self, guild_id: u64, patch: GuildSettingsPatch) -> Result<()>;
+        fn purge_guild(&self, guild_id: u64) -> Result<()>;
+        fn purge_shard(&self, shard_id: u64) -> Result<u64>;
+        fn push_choices(&self, guild_id: u64, blobs: Vec<String>) -> Result<()>;
+        fn push_queue(&self, guild_id: u64, item: String) -> Result<()>;
+        fn ratelimit_fixed(&self, guild_id: u64, user_id: u64, command: &str, limit: u64, window: Duration) -> Result<RateLimit>;
+        fn ratelimit_sliding(&self, guild_id: u64, user_id: u64, command: &str, limit: u64, window: Duration) -> Result<RateLimit>;
+        fn recent_messages(&self, channel_id: u64, n: usize) -> Result<Vec<CachedMessage>>;
+        fn record_lavalink_heartbeat(&self, address: &str, region: &str, players: u64, cpu_load: f64) -> Result<()>;
+        fn remove_lavalink_node(&self, address: &str) -> Result<()>;
+        fn remove_skip_vote(&self, guild_id: u64, user_id: u64) -> Result<()>;
+        fn repair_guild(&self, guild_id: u64) -> Result<GuildInconsistencies>;
+        fn requeue_from_history(&self, guild_id: u64, index: u64) -> Result<Option<HistoryEntry>>;
+        fn reset_guild_settings(&self, guild_id: u64) -> Result<()>;
+        fn reset_ratelimit(&self, guild_id: u64, user_id: u64, command: &str) -> Result<()>;
+        fn reset_skip_votes(&self, guild_id: u64) -> Result<()>;
+        fn resolve_member_tag(&self, guild_id: u64, tag: &str) -> Result<Option<u64>>;
+        fn search_members(&self, guild_id: u64, prefix: &str, limit: usize) -> Result<Vec<u64>>;
+        fn set_current_track(&self, guild_id: u64, track: Option<String>) -> Result<()>;
+        fn set_join(&self, guild_id: u64, channel: u64) -> Result<()>;
+        fn set_queue(&self, guild_id: u64, queue: Vec<String>) -> Result<()>;
+        fn shard_guild_count(&self, shard_id: u64) -> Result<u64>;
+        fn shard_guild_counts(&self) -> Result<HashMap<u64, u64>>;
+        fn sharder_msg(&self, shard_id: u64, data: Vec<u8>) -> Result<()>;
+        fn skip_vote_passes(&self, guild_id: u64, channel_id: u64, ratio: f64) -> Result<bool>;
+        fn skip_vote_tally(&self, guild_id: u64, channel_id: u64) -> Result<SkipVotes>;
+        fn update_emojis(&self, event: &GuildEmojisUpdateEvent) -> Result<()>;
+        fn update_message(&self, event: &MessageUpdateEvent) -> Result<bool>;
+        fn upsert_channel(&self, channel: &Channel) -> Result<()>;
+        fn upsert_emojis(&self, guild_id: u64, emojis: &HashMap<EmojiId, Emoji>) -> Result<()>;
+        fn upsert_guild(&self, guild: &Guild) -> Result<()>;
+        fn upsert_voice_state(&self, guild_id: u64, state: &VoiceState) -> Result<()>;
+        fn verify_guild(&self, guild_id: u64) -> Result<GuildInconsistencies>;
+    }
+    sync {
+        fn delete_channel(&self, id: u64);
+        fn delete_guild(&self, id: u64);
+        fn delete_guilds(&self, ids: impl IntoIterator<Item = u64>);
+        fn delete_member(&self, guild_id: u64, user_id: u64);
+        fn guild_shard_id(&self, guild_id: u64) -> Option<u64>;
+        fn push_history(&self, guild_id: u64, entry: &HistoryEntry) -> Result<()>;
+        fn set_loop_mode(&self, guild_id: u64, loop_mode: LoopMode);
+        fn shard_count(&self) -> Option<NonZeroU64>;
+        fn update_member(&self, event: &GuildMemberUpdateEvent);
+        fn update_presence(&self, event: &PresenceUpdateEvent);
+        fn update_user(&self, event: &UserUpdateEvent);
+        fn upsert_member(&self, member: &Member) -> Result<()>;
+        fn upsert_message(&self, message: &Message) -> Result<()>;
+        fn upsert_presence(&self, guild_id: u64, presence: &Presence);
+        fn upsert_user(&self, user: &User);
+        fn upsert_voice_state_info(&self, guild_id: u64, user_id: u64, endpoint: String, token: String);
+    }
+}
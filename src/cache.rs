@@ -1,14 +1,36 @@
 use crate::{
     commands::CommandablePairedConnection,
+    config::Config,
     error::{Error, Result},
-    gen,
+    gen::{self, Key},
+    hash::{HashChanges, RespHash as _, ToHashChanges as _},
     model::{
+        Emoji as CachedEmoji,
+        Guild as CachedGuild,
+        GuildInconsistencies,
+        GuildParts,
+        GuildSettings,
+        GuildSettingsPatch,
+        HistoryEntry,
+        Member as CachedMember,
+        MemberPatch,
+        Message as CachedMessage,
+        PlayerPatch,
+        Presence as CachedPresence,
+        Role as CachedRole,
+        User as CachedUser,
         VoiceState as CachedVoiceState,
+        VoiceStatePatch,
         LoopMode,
     },
     resp_impl::RespValueExt as _,
+    shard,
 };
 use essentials::result::ResultExt as _;
+use futures::{
+    future,
+    stream::{Stream, StreamExt as _},
+};
 use redis_async::{
     client::PairedConnection,
     resp::{FromResp, RespValue},
@@ -16,24 +38,188 @@ use redis_async::{
 use serde::de::DeserializeOwned;
 use serenity::model::prelude::*;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     convert::TryFrom,
+    num::NonZeroU64,
     sync::Arc,
+    time::Duration,
 };
 
+/// Replaces the content hash of a message in a channel's recent message list.
+///
+/// `KEYS[1]` is the message list. `ARGV[1]` is the message ID and `ARGV[2]`
+/// the new content hash.
+///
+/// Entries are decoded to compare their IDs, which `cjson` decodes as doubles,
+/// so IDs are only told apart as precisely as a double allows. The matching
+/// entry is edited as text rather than re-encoded, since that would round its
+/// ID and hash.
+///
+/// Returns whether the message was found.
+const UPDATE_MESSAGE_SCRIPT: &str = r#"
+local id = tonumber(ARGV[1])
+local entries = redis.call('LRANGE', KEYS[1], 0, -1)
+for i, entry in ipairs(entries) do
+    if cjson.decode(entry).id == id then
+        local updated = string.gsub(entry, '"content_hash":%d+', '"content_hash":' .. ARGV[2], 1)
+        redis.call('LSET', KEYS[1], i - 1, updated)
+        return 1
+    end
+end
+return 0
+"#;
+
+/// Removes messages from a channel's recent message list.
+///
+/// `KEYS[1]` is the message list and `ARGV` are the message IDs, compared as in
+/// the update script.
+///
+/// Returns the number of messages removed.
+const DELETE_MESSAGES_SCRIPT: &str = r#"
+local ids = {}
+for _, id in ipairs(ARGV) do
+    ids[tonumber(id)] = true
+end
+local count = 0
+for _, entry in ipairs(redis.call('LRANGE', KEYS[1], 0, -1)) do
+    if ids[cjson.decode(entry).id] then
+        count = count + redis.call('LREM', KEYS[1], 1, entry)
+    end
+end
+return count
+"#;
+
+/// Moves an entry from a guild's history to its queue.
+///
+/// `KEYS[1]` is the history and `KEYS[2]` the queue. `ARGV[1]` is the index of
+/// the entry in the history. The entry's track is pushed to the same end of
+/// the queue as [`Cache::push_queue`] does.
+///
+/// Returns the entry, or nil if there isn't one at the index.
+///
+/// [`Cache::push_queue`]: struct.Cache.html#method.push_queue
+const REQUEUE_SCRIPT: &str = r#"
+local entry = redis.call('LINDEX', KEYS[1], ARGV[1])
+if not entry then
+    return false
+end
+redis.call('LREM', KEYS[1], 1, entry)
+redis.call('LPUSH', KEYS[2], cjson.decode(entry).track)
+return entry
+"#;
+
+/// Re-evaluates whether a user counts as a listener in the voice channels
+/// they're in, such as after their user is first cached.
+///
+/// `KEYS[1]` is the user and `KEYS[2]` the Set of guilds they have voice
+/// states in, and `ARGV[1]` is the user ID. The voice state and listener keys
+/// depend on what the script reads, so they're built here in the same form as
+/// the `gen` module builds them. Guilds whose voice state is gone are removed
+/// from the Set.
+const REFRESH_LISTENER_SCRIPT: &str = r#"
+local bot = redis.call('HGET', KEYS[1], 'bot')
+for _, guild_id in ipairs(redis.call('SMEMBERS', KEYS[2])) do
+    local state = redis.call('HMGET', 'g:' .. guild_id .. ':v:' .. ARGV[1], 'channel_id', 'deaf', 'self_deaf')
+    if state[1] then
+        local listeners = 'ch:' .. state[1] .. ':l'
+        if bot == '0' and state[2] ~= '1' and state[3] ~= '1' then
+            redis.call('SADD', listeners, ARGV[1])
+        else
+            redis.call('SREM', listeners, ARGV[1])
+        end
+    else
+        redis.call('SREM', KEYS[2], guild_id)
+    end
+end
+return 0
+"#;
+
 /// A struct with common shared functionality over the bot's cache.
+#[derive(Clone)]
 pub struct Cache {
+    config: Config,
     inner: CommandablePairedConnection,
 }
 
 impl Cache {
     /// Creates a new cache accessing instance.
     pub fn new(redis: Arc<PairedConnection>) -> Self {
+        Self::with_config(redis, Config::default())
+    }
+
+    /// Creates a new cache accessing instance with the given configuration.
+    pub fn with_config(redis: Arc<PairedConnection>, config: Config) -> Self {
+        Self {
+            inner: CommandablePairedConnection::with_policy(
+                redis,
+                config.command_timeout,
+                config.retry,
+                config.on_write_error.clone(),
+            ),
+            config,
+        }
+    }
+
+    /// Creates a new cache accessing instance that knows the total number of
+    /// shards.
+    ///
+    /// Guilds are indexed by shard when upserted, allowing use of the
+    /// shard-scoped methods such as [`purge_shard`].
+    ///
+    /// [`purge_shard`]: #method.purge_shard
+    pub fn with_shard_count(
+        redis: Arc<PairedConnection>,
+        shard_count: NonZeroU64,
+    ) -> Self {
+        Self::with_config(redis, Config {
+            shard_count: Some(shard_count),
+            ..Config::default()
+        })
+    }
+
+    /// Returns the configuration of the cache.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Returns a handle to the same cache whose commands time out after the
+    /// given duration instead of the configured [`command_timeout`].
+    ///
+    /// This is cheap, so it can be used for a single call:
+    ///
+    /// ```rust,ignore
+    /// let guild = await!(cache.with_timeout(Some(Duration::from_secs(1))).get_guild(id))?;
+    /// ```
+    ///
+    /// [`command_timeout`]: struct.Config.html#structfield.command_timeout
+    pub fn with_timeout(&self, timeout: Option<Duration>) -> Self {
         Self {
-            inner: CommandablePairedConnection::new(redis),
+            config: Config {
+                command_timeout: timeout,
+                ..self.config.clone()
+            },
+            inner: self.inner.with_timeout(timeout),
         }
     }
 
+    /// Returns the total number of shards, if one was given.
+    pub fn shard_count(&self) -> Option<NonZeroU64> {
+        self.config.shard_count
+    }
+
+    /// Waits for all writes sent so far without waiting for their replies,
+    /// such as those of [`upsert_presence`], and returns the first of their
+    /// failures since the last flush.
+    ///
+    /// Failures are also logged and passed to the configured
+    /// [`on_write_error`] handler as they happen.
+    ///
+    /// [`on_write_error`]: struct.Config.html#structfield.on_write_error
+    /// [`upsert_presence`]: #method.upsert_presence
+    pub async fn flush(&self) -> Result<()> {
+        await!(self.inner.flush())
+    }
+
     /// Returns the inner commandable paired connection for use in lower level
     /// data manipulation.
     pub fn inner(&self) -> &CommandablePairedConnection {
@@ -123,15 +309,9 @@ impl Cache {
     ) -> Result<HashMap<u64, CachedVoiceState>> {
         let user_ids = await!(self.get_voice_state_list(guild_id))?;
 
-        let mut map = HashMap::new();
-
-        for id in user_ids {
-            let state = await!(self.get_voice_state(guild_id, id))??;
-
-            map.insert(id, state);
-        }
-
-        Ok(map)
+        await!(self.get_hashes(user_ids, |id| {
+            gen::user_voice_state(guild_id, id)
+        }))
     }
 
     /// Gets the IDs of all members that have a voice state in a channel.
@@ -150,18 +330,210 @@ impl Cache {
         Ok(numbers)
     }
 
-    /// Gets the IDs of all members that have a voice state in a guild.
-    pub async fn get_voice_state_list(
+    /// Returns the presence of a guild member, if they aren't offline.
+    pub async fn get_presence(
         &self,
         guild_id: u64,
-    ) -> Result<Vec<u64>> {
-        let resp = await!(self.inner.get(gen::guild_voice_states(guild_id)))?;
+        user_id: u64,
+    ) -> Result<Option<CachedPresence>> {
+        let value = await!(self.inner.hgetall(
+            gen::presence(guild_id, user_id),
+        ))?.into_array();
 
-        if resp == RespValue::Nil {
-            return Ok(vec![]);
+        if value.is_empty() {
+            return Ok(None);
         }
 
-        FromResp::from_resp(resp).into_err()
+        FromResp::from_resp(RespValue::Array(value)).map(Some).into_err()
+    }
+
+    /// Gets the presences of all members in a guild that aren't offline.
+    pub async fn get_guild_presences(
+        &self,
+        guild_id: u64,
+    ) -> Result<HashMap<u64, CachedPresence>> {
+        let user_ids = await!(self.get_ids(gen::guild_presences(guild_id)))?;
+
+        await!(self.get_hashes(user_ids, |id| gen::presence(guild_id, id)))
+    }
+
+    /// Returns a guild member, if they're cached.
+    ///
+    /// The member's globally cached user is joined in. As with
+    /// [`get_members`], a member whose user isn't cached is treated as not
+    /// cached.
+    ///
+    /// [`get_members`]: #method.get_members
+    pub async fn get_member(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+    ) -> Result<Option<crate::model::Member>> {
+        let values = await!(self.inner.hgetall(
+            gen::member(guild_id, user_id),
+        ))?.into_array();
+
+        if values.is_empty() {
+            return Ok(None);
+        }
+
+        let mut member = RespValue::Array(values);
+
+        let roles = await!(self.inner.smembers::<RespValue>(
+            gen::member_roles(guild_id, user_id),
+        ))?;
+        member.push("roles").push(roles);
+
+        let user = await!(self.inner.hgetall(gen::user(user_id)))?.into_array();
+
+        if user.is_empty() {
+            return Ok(None);
+        }
+
+        crate::model::Member::from_resp_with_user(member, RespValue::Array(user))
+            .map(Some)
+            .into_err()
+    }
+
+    /// Gets all of the cached members of a guild, keyed by user ID.
+    ///
+    /// Members whose user isn't cached are skipped.
+    pub async fn get_guild_members(
+        &self,
+        guild_id: u64,
+    ) -> Result<HashMap<u64, crate::model::Member>> {
+        let user_ids = await!(self.get_ids(gen::guild_members(guild_id)))?;
+
+        await!(self.get_members(guild_id, user_ids))
+    }
+
+    /// Gets multiple members of a guild in one round trip, keyed by user ID.
+    ///
+    /// Members that aren't cached, or whose user isn't cached, are skipped.
+    pub async fn get_members(
+        &self,
+        guild_id: u64,
+        user_ids: Vec<u64>,
+    ) -> Result<HashMap<u64, crate::model::Member>> {
+        let mut commands = Vec::with_capacity(user_ids.len() * 3);
+
+        for id in &user_ids {
+            commands.push(resp_array!["HGETALL", gen::member(guild_id, *id)]);
+            commands.push(resp_array!["SMEMBERS", gen::member_roles(guild_id, *id)]);
+            commands.push(resp_array!["HGETALL", gen::user(*id)]);
+        }
+
+        let mut replies = await!(self.inner.send_pipelined::<RespValue>(commands))?
+            .into_iter();
+        let mut map = HashMap::with_capacity(user_ids.len());
+
+        for id in user_ids {
+            let values = replies.next()?.into_array();
+            let roles = replies.next()?;
+            let user = replies.next()?.into_array();
+
+            if values.is_empty() || user.is_empty() {
+                continue;
+            }
+
+            let mut member = RespValue::Array(values);
+            member.push("roles").push(roles);
+
+            let member = crate::model::Member::from_resp_with_user(
+                member,
+                RespValue::Array(user),
+            ).into_err()?;
+
+            map.insert(id, member);
+        }
+
+        Ok(map)
+    }
+
+    /// Gets all of the roles of a guild, keyed by role ID.
+    pub async fn get_roles(
+        &self,
+        guild_id: u64,
+    ) -> Result<HashMap<u64, crate::model::Role>> {
+        let role_ids = await!(self.get_ids(gen::guild_roles(guild_id)))?;
+
+        await!(self.get_hashes(role_ids, |id| gen::role(guild_id, id)))
+    }
+
+    /// Gets a user from the global user cache.
+    pub async fn get_user(
+        &self,
+        user_id: u64,
+    ) -> Result<Option<crate::model::User>> {
+        let values = await!(self.inner.hgetall(gen::user(user_id)))?.into_array();
+
+        if values.is_empty() {
+            return Ok(None);
+        }
+
+        FromResp::from_resp(RespValue::Array(values)).map(Some).into_err()
+    }
+
+    /// Gets the custom emojis of a guild, keyed by emoji ID.
+    pub async fn get_guild_emojis(
+        &self,
+        guild_id: u64,
+    ) -> Result<HashMap<u64, CachedEmoji>> {
+        let emoji_ids = await!(self.get_ids(gen::guild_emojis(guild_id)))?;
+
+        let mut commands = Vec::with_capacity(emoji_ids.len() * 2);
+
+        for id in &emoji_ids {
+            commands.push(resp_array!["HGETALL", gen::emoji(guild_id, *id)]);
+            commands.push(resp_array!["SMEMBERS", gen::emoji_roles(guild_id, *id)]);
+        }
+
+        let mut replies = await!(self.inner.send_pipelined::<RespValue>(commands))?
+            .into_iter();
+        let mut map = HashMap::with_capacity(emoji_ids.len());
+
+        for id in emoji_ids {
+            let values = replies.next()?.into_array();
+            let roles = replies.next()?;
+
+            if values.is_empty() {
+                continue;
+            }
+
+            let mut value = RespValue::Array(values);
+            value.push("roles").push(roles);
+
+            map.insert(id, FromResp::from_resp(value).into_err()?);
+        }
+
+        Ok(map)
+    }
+
+    /// Gets the IDs of the members in a voice channel who are listening: those
+    /// who aren't bots and aren't deafened.
+    ///
+    /// Members whose user isn't cached aren't counted, since it isn't known
+    /// whether they're bots.
+    pub async fn channel_listeners(&self, channel_id: u64) -> Result<Vec<u64>> {
+        await!(self.get_ids(gen::channel_listeners(channel_id)))
+    }
+
+    /// Gets the number of members in a voice channel who are listening.
+    ///
+    /// This is cheaper than [`channel_listeners`] when only the count is
+    /// needed.
+    ///
+    /// [`channel_listeners`]: #method.channel_listeners
+    pub async fn channel_listener_count(&self, channel_id: u64) -> Result<u64> {
+        await!(self.inner.scard(gen::channel_listeners(channel_id)))
+    }
+
+    /// Gets the IDs of all members that have a voice state in a guild.
+    pub async fn get_voice_state_list(
+        &self,
+        guild_id: u64,
+    ) -> Result<Vec<u64>> {
+        await!(self.get_ids(gen::guild_voice_states(guild_id)))
     }
 
     /// Gets the choices available for a guild.
@@ -292,13 +664,10 @@ impl Cache {
         guild_id: u64,
         loop_mode: LoopMode,
     ) {
-        let key = gen::guild_player(guild_id);
-
-        let mode: String = loop_mode.into();
-
-        let value = resp_array!["loop_mode", mode];
-
-        self.inner.hmset_sync(key, value.into_array());
+        self.set_hash(gen::guild_player(guild_id), PlayerPatch {
+            loop_mode: Some(loop_mode),
+            ..PlayerPatch::default()
+        }.into_changes());
     }
 }
 
@@ -344,6 +713,11 @@ impl Cache {
     }
 
     pub fn delete_guild(&self, id: u64) {
+        if let Some(shard_id) = self.guild_shard_id(id) {
+            self.inner.srem_sync(gen::shard_guilds(shard_id), vec![id as usize]);
+        }
+
+        self.inner.srem_sync(gen::guilds(), vec![id as usize]);
         self.inner.del_sync(gen::guild(id))
     }
 
@@ -351,6 +725,13 @@ impl Cache {
         &self,
         ids: impl IntoIterator<Item = u64>,
     ) {
+        let ids = ids.into_iter().collect::<Vec<_>>();
+
+        if ids.is_empty() {
+            return;
+        }
+
+        self.inner.srem_sync(gen::guilds(), ids.iter().map(|id| *id as usize).collect());
         self.inner.delm_sync(ids.into_iter().map(gen::guild))
     }
 
@@ -367,29 +748,47 @@ impl Cache {
         }).collect()))
     }
 
+    /// Gets a guild with all of its parts.
+    ///
+    /// Prefer [`get_guild_with`] when not all of the parts are needed.
+    ///
+    /// [`get_guild_with`]: #method.get_guild_with
     pub async fn get_guild(&self, id: u64) -> Result<crate::model::Guild> {
-        let values = await!(self.inner.hgetall(gen::guild(id)))?.into_array();
+        await!(self.get_guild_with(id, GuildParts::all()))
+    }
 
-        if values.is_empty() {
-            return Err(Error::None);
-        }
+    /// Gets a guild with only the selected parts, in one round trip.
+    ///
+    /// Returns [`Error::None`] if the guild isn't cached.
+    ///
+    /// [`Error::None`]: enum.Error.html#variant.None
+    pub async fn get_guild_with(
+        &self,
+        id: u64,
+        parts: GuildParts,
+    ) -> Result<crate::model::Guild> {
+        let parts = parts.keys(id);
 
-        let mut values = RespValue::Array(values);
+        let mut commands = Vec::with_capacity(parts.len() + 1);
+        commands.push(resp_array!["HGETALL", gen::guild(id)]);
+        commands.extend(parts.iter().map(|(_, key)| {
+            resp_array!["SMEMBERS", key.clone()]
+        }));
 
-        let channels = await!(self.inner.smembers::<RespValue>(gen::guild_channels(id)))?;
-        values.push("channels").push(channels);
+        let mut replies = await!(self.inner.send_pipelined::<RespValue>(commands))?
+            .into_iter();
 
-        let features = await!(self.inner.smembers::<RespValue>(gen::guild_features(id)))?;
-        values.push("features").push(features);
+        let values = replies.next()?.into_array();
 
-        let members = await!(self.inner.smembers::<RespValue>(gen::guild_members(id)))?;
-        values.push("members").push(members);
+        if values.is_empty() {
+            return Err(Error::None);
+        }
 
-        let roles = await!(self.inner.smembers::<RespValue>(gen::guild_roles(id)))?;
-        values.push("roles").push(roles);
+        let mut values = RespValue::Array(values);
 
-        let voice_states = await!(self.inner.smembers::<RespValue>(gen::guild_voice_states(id)))?;
-        values.push("voice_states").push(voice_states);
+        for ((name, _), members) in parts.into_iter().zip(replies) {
+            values.push(name).push(members);
+        }
 
         FromResp::from_resp(values).into_err()
     }
@@ -412,36 +811,28 @@ impl Cache {
         let gid = guild.id.0;
         info!("Upserting guild ID {}", gid);
 
-        let mut set = resp_array![
-            "HMSET",
-            gen::guild(gid),
-            "name",
-            &guild.name,
-            "owner_id",
-            guild.owner_id.0 as usize,
-            "region",
-            &guild.region
-        ];
-        let mut del = None;
+        let cached = CachedGuild {
+            afk_channel_id: guild.afk_channel_id.map(|id| id.0),
+            name: guild.name.clone(),
+            owner_id: guild.owner_id.0,
+            region: guild.region.clone(),
+            ..CachedGuild::default()
+        };
 
-        if let Some(afk_channel_id) = guild.afk_channel_id {
-            set.push("afk_channel_id".to_owned()).push(afk_channel_id.0 as usize);
-        } else {
-            del = Some(vec![
-                "afk_channel_id",
-            ]);
-        }
+        info!("Sending guild upsert hash");
+        self.set_hash(gen::guild(gid), cached.to_hash_changes());
+        info!("Guild upsert hash successful");
 
-        info!("Sending guild upsert HMSET");
-        self.inner.send_sync(set);
-        info!("Guild upsert HMSET successful");
+        self.inner.sadd_sync(gen::guilds(), vec![gid as usize]);
 
-        if let Some(del) = del {
-            info!("Sending guild upsert HDEL");
-            self.inner.hdel_sync(gen::guild(gid), del);
-            info!("Sent guild upsert HDEL");
+        if let Some(shard_id) = self.guild_shard_id(gid) {
+            self.inner.sadd_sync(gen::shard_guilds(shard_id), vec![gid as usize]);
         }
 
+        // Channels that were deleted while the guild was unavailable still
+        // need their voice states and listeners cleared.
+        let old_channel_ids = await!(self.get_ids(gen::guild_channels(gid)))?;
+
         info!("Sending guild set channels");
         self.set_guild_channels(
             gid,
@@ -449,6 +840,10 @@ impl Cache {
         );
         info!("Guild set channels successful");
 
+        info!("Upserting guild emojis");
+        await!(self.upsert_emojis(gid, &guild.emojis))?;
+        info!("Guild emojis' upsert complete");
+
         info!("Sending guild set features");
         self.set_guild_features(gid, guild.features.clone());
         info!("Guild set features successful");
@@ -498,75 +893,270 @@ impl Cache {
                 return acc;
             });
 
+        let channel_ids = old_channel_ids
+            .into_iter()
+            .chain(guild.channels.keys().map(|id| id.0))
+            .collect::<HashSet<_>>();
+
+        for id in channel_ids {
+            if !channel_states.contains_key(&id) {
+                self.inner.del_sync(gen::channel_listeners(id));
+                self.inner.del_sync(gen::channel_voice_states(id));
+            }
+        }
+
         for (id, user_ids) in channel_states {
+            self.inner.del_sync(gen::channel_listeners(id));
             self.set_channel_voice_states(id, user_ids);
         }
 
         info!("Upserting guild voice states");
         for state in guild.voice_states.values() {
-            self.upsert_voice_state(gid, state);
+            await!(self.upsert_voice_state(gid, state))?;
         }
         info!("Guild voice states' upsert complete");
 
+        info!("Upserting guild presences");
+        // Members who went offline while the guild was unavailable aren't in
+        // its presences, so their old ones have to be removed.
+        let old_presence_ids = await!(self.get_ids(gen::guild_presences(gid)))?;
+        self.inner.delm_sync(old_presence_ids.into_iter().map(|id| gen::presence(gid, id)));
+        self.inner.del_sync(gen::guild_presences(gid));
+        for presence in guild.presences.values() {
+            self.upsert_presence(gid, presence);
+        }
+        info!("Guild presences' upsert complete");
+
         Ok(())
     }
 
-    fn upsert_member<'a>(&'a self, member: &'a Member) -> Result<()> {
-        let guild_id = member.guild_id.0;
-        let user_id = member.user.id.0;
+    /// Replaces a guild's emojis from a GuildEmojisUpdate event.
+    pub async fn update_emojis<'a>(
+        &'a self,
+        event: &'a GuildEmojisUpdateEvent,
+    ) -> Result<()> {
+        await!(self.upsert_emojis(event.guild_id.0, &event.emojis))
+    }
 
-        let mut set = resp_array![
-            "deaf",
-            usize::from(member.deaf),
-            "mute",
-            usize::from(member.mute),
-            "user_id",
-            user_id as usize
-        ];
+    /// Replaces a guild's emojis.
+    ///
+    /// Emojis that were cached for the guild but aren't in the given map are
+    /// removed.
+    pub async fn upsert_emojis<'a>(
+        &'a self,
+        guild_id: u64,
+        emojis: &'a HashMap<EmojiId, Emoji>,
+    ) -> Result<()> {
+        let old_ids = await!(self.get_ids(gen::guild_emojis(guild_id)))?;
+
+        for id in old_ids {
+            if !emojis.contains_key(&EmojiId(id)) {
+                self.inner.delm_sync(vec![
+                    gen::emoji(guild_id, id),
+                    gen::emoji_roles(guild_id, id),
+                ]);
+            }
+        }
 
-        if let Some(joined_at) = member.joined_at {
-            let ser = serde_json::to_vec(&joined_at)?;
+        for emoji in emojis.values() {
+            let id = emoji.id.0;
+            let cached = CachedEmoji {
+                animated: emoji.animated,
+                id,
+                name: emoji.name.clone(),
+                roles: emoji.roles.iter().map(|x| x.0).collect(),
+            };
+
+            self.set_hash(gen::emoji(guild_id, id), cached.to_hash_changes());
+
+            let key = gen::emoji_roles(guild_id, id);
+            self.inner.del_sync(key.clone());
+            self.inner.sadd_sync(
+                key,
+                emoji.roles.iter().map(|x| x.0 as usize).collect::<Vec<_>>(),
+            );
+        }
+
+        let key = gen::guild_emojis(guild_id);
+        self.inner.del_sync(key.clone());
+        self.inner.sadd_sync(
+            key,
+            emojis.keys().map(|x| x.0 as usize).collect::<Vec<_>>(),
+        );
+
+        Ok(())
+    }
 
-            set.push("joined_at").push(ser);
+    /// Updates a guild member's presence from a PresenceUpdate event.
+    ///
+    /// Presence updates for users outside of a guild are ignored.
+    pub fn update_presence<'a>(&'a self, event: &'a PresenceUpdateEvent) {
+        if let Some(guild_id) = event.guild_id {
+            self.upsert_presence(guild_id.0, &event.presence);
         }
+    }
 
-        if let Some(nick) = member.nick.as_ref() {
-            set.push("nick").push(nick);
-        } else {
-            self.inner.hdel_sync(
-                gen::member(guild_id, user_id),
-                vec!["afk_channel_id"],
+    /// Updates a guild member's presence.
+    ///
+    /// Offline members have their presence removed rather than stored, so a
+    /// missing presence can be treated as offline.
+    pub fn upsert_presence<'a>(
+        &'a self,
+        guild_id: u64,
+        presence: &'a Presence,
+    ) {
+        let user_id = presence.user_id.0;
+        let key = gen::presence(guild_id, user_id);
+
+        if presence.status == OnlineStatus::Offline {
+            self.inner.srem_sync(
+                gen::guild_presences(guild_id),
+                vec![user_id as usize],
             );
+            self.inner.del_sync(key);
+
+            return;
         }
 
-        self.inner.hmset_sync(gen::member(guild_id, user_id), set.into_array());
+        let game = presence.game.as_ref();
+        let cached = CachedPresence {
+            activity_kind: game.map(|game| game.kind.num()),
+            activity_name: game.map(|game| game.name.clone()),
+            status: presence.status.name().to_owned(),
+        };
+
+        self.set_hash(key, cached.to_hash_changes());
+        self.inner.sadd_sync(
+            gen::guild_presences(guild_id),
+            vec![user_id as usize],
+        );
+    }
+
+    /// Updates a guild member, including their globally cached user.
+    pub fn upsert_member<'a>(&'a self, member: &'a Member) -> Result<()> {
+        let guild_id = member.guild_id.0;
+        let user_id = member.user.id.0;
+
+        let cached = CachedMember {
+            deaf: member.deaf,
+            joined_at: member.joined_at,
+            mute: member.mute,
+            nick: member.nick.clone(),
+            roles: member.roles.iter().map(|x| x.0).collect(),
+            user: CachedUser::from(&member.user),
+            user_id,
+        };
+
+        self.set_hash(gen::member(guild_id, user_id), cached.to_resp_hash()?);
 
         self.set_member_roles(
             guild_id,
             user_id,
-            member.roles.iter().map(|x| x.0 as usize).collect(),
+            cached.roles.iter().map(|id| *id as usize).collect(),
+        );
+
+        self.set_user(&cached.user);
+        self.index_member(
+            guild_id,
+            user_id,
+            &member.user.name,
+            member.user.discriminator,
+            member.nick.as_ref().map(AsRef::as_ref),
         );
 
         Ok(())
     }
 
+    /// Removes a guild member, such as from a GuildMemberRemove event.
+    ///
+    /// The member's globally cached user is kept, as they may share other
+    /// guilds with the bot.
+    pub fn delete_member(&self, guild_id: u64, user_id: u64) {
+        self.inner.srem_sync(
+            gen::guild_members(guild_id),
+            vec![user_id as usize],
+        );
+        self.inner.delm_sync(vec![
+            gen::member(guild_id, user_id),
+            gen::member_roles(guild_id, user_id),
+        ]);
+        self.unindex_member(guild_id, user_id);
+    }
+
+    /// Updates a guild member from a GuildMemberUpdate event.
+    pub fn update_member<'a>(&'a self, event: &'a GuildMemberUpdateEvent) {
+        let guild_id = event.guild_id.0;
+        let user_id = event.user.id.0;
+
+        self.set_hash(gen::member(guild_id, user_id), MemberPatch {
+            nick: Some(event.nick.clone()),
+        }.into_changes());
+
+        self.set_member_roles(
+            guild_id,
+            user_id,
+            event.roles.iter().map(|x| x.0 as usize).collect(),
+        );
+
+        self.upsert_user(&event.user);
+        self.index_member(
+            guild_id,
+            user_id,
+            &event.user.name,
+            event.user.discriminator,
+            event.nick.as_ref().map(AsRef::as_ref),
+        );
+    }
+
+    /// Updates a user in the global user cache.
+    ///
+    /// Users are stored once regardless of how many guilds they share with
+    /// the bot, and are joined into members when they're read.
+    pub fn upsert_user<'a>(&'a self, user: &'a User) {
+        self.set_user(&CachedUser::from(user));
+    }
+
+    /// Updates the current user in the global user cache from a UserUpdate
+    /// event.
+    pub fn update_user<'a>(&'a self, event: &'a UserUpdateEvent) {
+        self.set_user(&CachedUser::from(&event.current_user));
+    }
+
+    /// Returns whether a user is a bot, or `None` if the user isn't cached.
+    async fn is_bot(&self, user_id: u64) -> Result<Option<bool>> {
+        let bot: Option<String> = await!(self.inner.hget(
+            gen::user(user_id),
+            "bot".to_owned(),
+        ))?;
+
+        Ok(bot.map(|bot| bot == "1"))
+    }
+
+    fn set_user(&self, user: &CachedUser) {
+        self.set_hash(gen::user(user.id), user.to_hash_changes());
+
+        // Users who joined a voice channel before they were cached weren't
+        // counted as listeners, and a changed bot flag changes whether they
+        // count.
+        self.inner.eval_sync(
+            REFRESH_LISTENER_SCRIPT,
+            vec![gen::user(user.id), gen::user_voice_guilds(user.id)],
+            vec![RespValue::from(user.id.to_string())],
+        );
+    }
+
     fn upsert_role<'a>(
         &'a self,
         guild_id: u64,
         role: &'a Role,
     ) {
-        let id = role.id.0;
-
-        let hashes = resp_array![
-            "colour",
-            role.colour.0 as usize,
-            "name",
-            role.name.clone(),
-            "permissions",
-            role.permissions.bits() as usize
-        ];
+        let cached = CachedRole {
+            colour: role.colour.0,
+            name: role.name.clone(),
+            permissions: role.permissions,
+        };
 
-        self.inner.hmset_sync(gen::role(guild_id, id), hashes.into_array());
+        self.set_hash(gen::role(guild_id, role.id.0), cached.to_hash_changes());
     }
 
     pub async fn upsert_voice_state<'a>(
@@ -585,28 +1175,36 @@ impl Cache {
             let channel_id = channel_id.0;
             trace!("Voice state has a channel ID: {}", channel_id);
 
-            let mut values = resp_array![
-                "channel_id",
-                channel_id as usize,
-                "mute",
-                usize::from(state.mute),
-                "self_deaf",
-                usize::from(state.self_deaf),
-                "self_mute",
-                usize::from(state.self_mute),
-                "session_id",
-                state.session_id.clone(),
-                "suppress",
-                usize::from(state.suppress)
-            ];
-
-            if let Some(token) = state.token.as_ref() {
-                values.push("token".to_owned()).push(token);
-            } else {
-                self.inner.hdel_sync(key.clone(), vec!["token"]);
+            let cached = CachedVoiceState {
+                channel_id,
+                deaf: state.deaf,
+                mute: state.mute,
+                self_deaf: state.self_deaf,
+                self_mute: state.self_mute,
+                session_id: state.session_id.clone(),
+                suppress: state.suppress,
+                token: state.token.clone(),
+            };
+
+            let new_session = old_state.as_ref().map_or(true, |old| {
+                old.session_id != state.session_id
+            });
+
+            // The token and endpoint belong to the old session, so only the
+            // next voice server update can complete the new one.
+            if old_state.is_some() && new_session {
+                self.set_hash(key.clone(), VoiceStatePatch {
+                    endpoint: Some(None),
+                    token: Some(None),
+                }.into_changes());
             }
 
-            self.inner.hmset_sync(key, values.into_array());
+            self.set_hash(key, cached.to_hash_changes());
+
+            // The voice server update may have arrived first.
+            if new_session {
+                self.publish_voice_connection(guild_id, user_id);
+            }
 
             let mut add_member = true;
 
@@ -620,6 +1218,10 @@ impl Cache {
                         gen::channel_voice_states(old_cid),
                         vec![user_id as usize],
                     );
+                    self.inner.srem_sync(
+                        gen::channel_listeners(old_cid),
+                        vec![user_id as usize],
+                    );
                 } else {
                     add_member = false;
                 }
@@ -631,27 +1233,64 @@ impl Cache {
                     vec![user_id as usize],
                 );
             }
-        } else {
-            trace!("No channel ID for voice state");
-            if let Some(channel_id) = old_state.map(|s| s.channel_id) {
-                trace!("Deleting old voice state for channel {}", channel_id);
+
+            self.inner.sadd_sync(
+                gen::user_voice_guilds(user_id),
+                vec![guild_id as usize],
+            );
+
+            let deafened = state.deaf || state.self_deaf;
+            let bot = await!(self.is_bot(user_id))?;
+
+            // Users that aren't cached might be bots, so they aren't counted
+            // until they are.
+            if deafened || bot != Some(false) {
+                self.inner.srem_sync(
+                    gen::channel_listeners(channel_id),
+                    vec![user_id as usize],
+                );
+            } else {
+                self.inner.sadd_sync(
+                    gen::channel_listeners(channel_id),
+                    vec![user_id as usize],
+                );
+            }
+        } else {
+            trace!("No channel ID for voice state");
+            if let Some(channel_id) = old_state.map(|s| s.channel_id) {
+                trace!("Deleting old voice state for channel {}", channel_id);
 
                 self.inner.srem_sync(
                     gen::channel_voice_states(channel_id),
                     vec![user_id as usize],
                 );
+                self.inner.srem_sync(
+                    gen::channel_listeners(channel_id),
+                    vec![user_id as usize],
+                );
             }
 
             self.inner.srem_sync(
                 gen::guild_voice_states(guild_id),
                 vec![user_id as usize],
             );
+            self.inner.srem_sync(
+                gen::user_voice_guilds(user_id),
+                vec![guild_id as usize],
+            );
             self.inner.del_sync(key);
         }
 
         Ok(())
     }
 
+    /// Caches the voice server's endpoint and token for a user, normally the
+    /// bot, from a voice server update.
+    ///
+    /// If the user's voice state is also cached, the completed connection
+    /// info is published to [`subscribe_voice_connections`] subscribers.
+    ///
+    /// [`subscribe_voice_connections`]: #method.subscribe_voice_connections
     pub fn upsert_voice_state_info<'a>(
         &'a self,
         guild_id: u64,
@@ -659,14 +1298,23 @@ impl Cache {
         endpoint: String,
         token: String,
     ) {
-        let key = gen::user_voice_state(guild_id, user_id);
+        self.set_hash(gen::user_voice_state(guild_id, user_id), VoiceStatePatch {
+            endpoint: Some(Some(endpoint)),
+            token: Some(Some(token)),
+        }.into_changes());
+        self.publish_voice_connection(guild_id, user_id);
+    }
 
-        self.inner.hmset_sync(key, resp_array![
-            "endpoint",
-            endpoint,
-            "token",
-            token
-        ].into_array());
+    /// Applies a model's hash changes, deleting fields before setting the
+    /// rest.
+    fn set_hash(&self, key: String, changes: HashChanges) {
+        if !changes.delete.is_empty() {
+            self.inner.hdel_sync(key.clone(), changes.delete);
+        }
+
+        if !changes.set.is_empty() {
+            self.inner.hmset_sync(key, changes.set);
+        }
     }
 
     fn set_channel_voice_states(
@@ -747,3 +1395,590 @@ impl Cache {
         self.inner.sadd_sync(key, roles);
     }
 }
+
+/// Iteration over large Sets.
+///
+/// These use cursor-based scanning so that Redis isn't blocked by one large
+/// reply. Items may be yielded more than once.
+impl Cache {
+    /// Iterates over the IDs of all cached guilds.
+    ///
+    /// Guilds are indexed when upserted. Guilds cached before the index
+    /// existed are only included once they're upserted again, or once
+    /// [`index_guild_ids`] has been run.
+    ///
+    /// [`index_guild_ids`]: #method.index_guild_ids
+    pub fn iter_guild_ids(&self) -> impl Stream<Item = Result<u64>> {
+        self.inner.sscan(gen::guilds(), 1000).map(|id| {
+            id.and_then(|id| id.parse().map_err(From::from))
+        })
+    }
+
+    /// Adds every cached guild to the index read by [`iter_guild_ids`], by
+    /// scanning the keyspace for guilds.
+    ///
+    /// This only needs to be run once, after upgrading from a version that
+    /// didn't index guilds. It scans every guild-scoped key, so it's slow on
+    /// large caches.
+    ///
+    /// Returns the number of guilds found.
+    ///
+    /// [`iter_guild_ids`]: #method.iter_guild_ids
+    pub async fn index_guild_ids(&self) -> Result<u64> {
+        let ids = await!(self.inner.scan("g:*".to_owned(), 1000)
+            .filter_map(|key| future::ready(match key {
+                Ok(key) => match key.parse::<Key>() {
+                    Ok(Key::Guild(id)) => Some(Ok(id as usize)),
+                    _ => None,
+                },
+                Err(why) => Some(Err(why)),
+            }))
+            .collect::<Vec<Result<usize>>>());
+        let ids = ids.into_iter().collect::<Result<HashSet<_>>>()?;
+
+        if !ids.is_empty() {
+            await!(self.inner.sadd(gen::guilds(), ids.iter().cloned()))?;
+        }
+
+        Ok(ids.len() as u64)
+    }
+
+    /// Iterates over the IDs of the members of a guild.
+    pub fn iter_guild_member_ids(
+        &self,
+        guild_id: u64,
+    ) -> impl Stream<Item = Result<u64>> {
+        self.inner.sscan(gen::guild_members(guild_id), 1000).map(|id| {
+            id.and_then(|id| id.parse().map_err(From::from))
+        })
+    }
+}
+
+/// Consistency checks.
+impl Cache {
+    /// Checks a guild's cached data for dangling references.
+    ///
+    /// This looks for IDs in the guild's member Set with no member hash,
+    /// users in a channel's voice state Set whose voice state is missing or
+    /// for another channel, and role IDs in member role Sets that aren't in
+    /// the guild's role Set.
+    ///
+    /// Nothing is modified; use [`repair_guild`] to remove what is found.
+    ///
+    /// [`repair_guild`]: #method.repair_guild
+    pub async fn verify_guild(
+        &self,
+        guild_id: u64,
+    ) -> Result<GuildInconsistencies> {
+        let mut report = GuildInconsistencies::default();
+
+        let member_ids = await!(self.get_ids(gen::guild_members(guild_id)))?;
+        let role_ids = await!(self.get_ids(gen::guild_roles(guild_id)))?;
+
+        for user_id in member_ids {
+            let key = gen::member(guild_id, user_id);
+
+            if !await!(self.inner.exists(key))? {
+                report.dangling_members.push(user_id);
+
+                continue;
+            }
+
+            let member_roles = await!(self.get_ids(
+                gen::member_roles(guild_id, user_id),
+            ))?;
+
+            for role_id in member_roles {
+                if !role_ids.contains(&role_id) {
+                    report.dangling_member_roles.push((user_id, role_id));
+                }
+            }
+        }
+
+        let channel_ids = await!(self.get_ids(gen::guild_channels(guild_id)))?;
+
+        for channel_id in channel_ids {
+            let user_ids = await!(self.get_channel_voice_states(channel_id))?;
+
+            for user_id in user_ids {
+                let state = await!(self.get_voice_state(guild_id, user_id))?;
+
+                match state {
+                    Some(ref state) if state.channel_id == channel_id => {},
+                    _ => {
+                        report.stale_channel_voice_states.push((
+                            channel_id,
+                            user_id,
+                        ));
+                    },
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Checks a guild's cached data for dangling references and removes them.
+    ///
+    /// Returns the inconsistencies that were found and removed.
+    pub async fn repair_guild(
+        &self,
+        guild_id: u64,
+    ) -> Result<GuildInconsistencies> {
+        let report = await!(self.verify_guild(guild_id))?;
+
+        if report.is_empty() {
+            return Ok(report);
+        }
+
+        info!("Repairing guild {}: {:?}", guild_id, report);
+
+        if !report.dangling_members.is_empty() {
+            await!(self.inner.srem(
+                gen::guild_members(guild_id),
+                report.dangling_members.iter().map(|id| *id as usize).collect(),
+            ))?;
+            await!(self.inner.delm(report.dangling_members
+                .iter()
+                .map(|id| gen::member_roles(guild_id, *id))
+                .collect::<Vec<_>>()))?;
+
+            for user_id in &report.dangling_members {
+                self.unindex_member(guild_id, *user_id);
+            }
+        }
+
+        for (user_id, role_id) in &report.dangling_member_roles {
+            await!(self.inner.srem(
+                gen::member_roles(guild_id, *user_id),
+                vec![*role_id as usize],
+            ))?;
+        }
+
+        for (channel_id, user_id) in &report.stale_channel_voice_states {
+            await!(self.inner.srem(
+                gen::channel_voice_states(*channel_id),
+                vec![*user_id as usize],
+            ))?;
+        }
+
+        Ok(report)
+    }
+
+    /// Reads the hashes for multiple IDs in one round trip, keyed by ID.
+    ///
+    /// IDs with no hash are skipped.
+    async fn get_hashes<T: FromResp + 'static, F: Fn(u64) -> String>(
+        &self,
+        ids: Vec<u64>,
+        key: F,
+    ) -> Result<HashMap<u64, T>> {
+        let keys = ids.iter().map(|id| key(*id)).collect();
+        let values = await!(self.inner.hgetall_pipelined(keys))?;
+
+        let mut map = HashMap::with_capacity(ids.len());
+
+        for (id, value) in ids.into_iter().zip(values) {
+            let value = value.into_array();
+
+            if value.is_empty() {
+                continue;
+            }
+
+            map.insert(id, FromResp::from_resp(RespValue::Array(value)).into_err()?);
+        }
+
+        Ok(map)
+    }
+
+    async fn get_ids(&self, key: String) -> Result<Vec<u64>> {
+        let ids = await!(self.inner.smembers::<Vec<String>>(key))?;
+
+        let mut numbers = Vec::with_capacity(ids.len());
+
+        for id in ids {
+            numbers.push(id.parse()?);
+        }
+
+        Ok(numbers)
+    }
+}
+
+/// Shard-scoped operations.
+///
+/// These require the cache to have been created via
+/// [`Cache::with_shard_count`].
+///
+/// [`Cache::with_shard_count`]: struct.Cache.html#method.with_shard_count
+impl Cache {
+    /// Returns the ID of the shard that a guild is on, if the shard count is
+    /// known.
+    pub fn guild_shard_id(&self, guild_id: u64) -> Option<u64> {
+        self.config.shard_count.map(|count| shard::shard_id(guild_id, count))
+    }
+
+    /// Gets the IDs of all cached guilds on a shard.
+    pub async fn guilds_for_shard(&self, shard_id: u64) -> Result<Vec<u64>> {
+        await!(self.get_ids(gen::shard_guilds(shard_id)))
+    }
+
+    /// Gets the number of cached guilds on a shard.
+    pub async fn shard_guild_count(&self, shard_id: u64) -> Result<u64> {
+        await!(self.inner.scard(gen::shard_guilds(shard_id)))
+    }
+
+    /// Gets the number of cached guilds on each shard, keyed by shard ID.
+    ///
+    /// Returns [`Error::None`] if the shard count isn't known.
+    ///
+    /// [`Error::None`]: enum.Error.html#variant.None
+    pub async fn shard_guild_counts(&self) -> Result<HashMap<u64, u64>> {
+        let shard_count = self.config.shard_count?.get();
+        let mut counts = HashMap::with_capacity(shard_count as usize);
+
+        for shard_id in 0..shard_count {
+            counts.insert(shard_id, await!(self.shard_guild_count(shard_id))?);
+        }
+
+        Ok(counts)
+    }
+
+    /// Removes all cached data for the guilds on a shard.
+    ///
+    /// This should be used when a shard reconnects without resuming, as the
+    /// guilds will be re-sent in new GuildCreate events.
+    ///
+    /// Returns the number of guilds purged.
+    pub async fn purge_shard(&self, shard_id: u64) -> Result<u64> {
+        let guild_ids = await!(self.guilds_for_shard(shard_id))?;
+        let count = guild_ids.len();
+
+        for guild_id in guild_ids {
+            await!(self.purge_guild(guild_id))?;
+        }
+
+        await!(self.inner.del(gen::shard_guilds(shard_id)))?;
+
+        Ok(count as u64)
+    }
+
+    /// Removes all cached data for a guild, including its members, roles, and
+    /// voice states.
+    pub async fn purge_guild(&self, guild_id: u64) -> Result<()> {
+        let channel_ids = await!(self.get_ids(gen::guild_channels(guild_id)))?;
+        let emoji_ids = await!(self.get_ids(gen::guild_emojis(guild_id)))?;
+        let member_ids = await!(self.get_ids(gen::guild_members(guild_id)))?;
+        let presence_ids = await!(self.get_ids(gen::guild_presences(guild_id)))?;
+        let role_ids = await!(self.get_ids(gen::guild_roles(guild_id)))?;
+        let voice_state_ids = await!(self.get_ids(
+            gen::guild_voice_states(guild_id),
+        ))?;
+
+        let mut keys = vec![
+            gen::guild(guild_id),
+            gen::guild_channels(guild_id),
+            gen::guild_emojis(guild_id),
+            gen::guild_features(guild_id),
+            gen::guild_member_search(guild_id),
+            gen::guild_members(guild_id),
+            gen::guild_presences(guild_id),
+            gen::guild_roles(guild_id),
+            gen::guild_voice_states(guild_id),
+        ];
+
+        keys.extend(channel_ids.iter().map(|id| gen::channel_listeners(*id)));
+        keys.extend(channel_ids.into_iter().map(gen::channel_voice_states));
+        keys.extend(emoji_ids.iter().map(|id| gen::emoji(guild_id, *id)));
+        keys.extend(emoji_ids.iter().map(|id| gen::emoji_roles(guild_id, *id)));
+        keys.extend(member_ids.iter().map(|id| gen::member(guild_id, *id)));
+        keys.extend(member_ids.iter().map(|id| gen::member_roles(guild_id, *id)));
+        keys.extend(member_ids.iter().map(|id| gen::member_search_entries(guild_id, *id)));
+        keys.extend(presence_ids.into_iter().map(|id| gen::presence(guild_id, id)));
+        keys.extend(role_ids.into_iter().map(|id| gen::role(guild_id, id)));
+        keys.extend(voice_state_ids.into_iter().map(|id| {
+            gen::user_voice_state(guild_id, id)
+        }));
+
+        self.inner.delm_sync(keys);
+        self.inner.srem_sync(gen::guilds(), vec![guild_id as usize]);
+
+        if let Some(shard_id) = self.guild_shard_id(guild_id) {
+            self.inner.srem_sync(
+                gen::shard_guilds(shard_id),
+                vec![guild_id as usize],
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Recent messages.
+impl Cache {
+    /// Gets up to `n` of the most recent messages in a channel, newest first.
+    pub async fn recent_messages(
+        &self,
+        channel_id: u64,
+        n: usize,
+    ) -> Result<Vec<CachedMessage>> {
+        if n == 0 {
+            return Ok(vec![]);
+        }
+
+        let entries = await!(self.get_message_entries(channel_id, n as i64 - 1))?;
+
+        let mut messages = Vec::with_capacity(entries.len());
+
+        for entry in entries {
+            messages.push(serde_json::from_slice(&entry)?);
+        }
+
+        Ok(messages)
+    }
+
+    /// Pushes a message to its channel's recent message list from a
+    /// MessageCreate event.
+    ///
+    /// The list is trimmed to the configured
+    /// [`message_cache_size`][`Config::message_cache_size`], and its TTL is
+    /// refreshed if [`message_ttl`][`Config::message_ttl`] is set.
+    ///
+    /// [`Config::message_cache_size`]: struct.Config.html#structfield.message_cache_size
+    /// [`Config::message_ttl`]: struct.Config.html#structfield.message_ttl
+    pub fn upsert_message<'a>(&'a self, message: &'a Message) -> Result<()> {
+        let size = self.config.message_cache_size;
+
+        if size == 0 {
+            return Ok(());
+        }
+
+        let cached = CachedMessage {
+            author_id: message.author.id.0,
+            content_hash: CachedMessage::hash_content(&message.content),
+            id: message.id.0,
+            timestamp: message.timestamp.timestamp(),
+        };
+        let key = gen::channel_messages(message.channel_id.0);
+
+        self.inner.lpush_sync(key.clone(), vec![serde_json::to_vec(&cached)?]);
+        self.inner.ltrim_sync(key.clone(), 0, size as i64 - 1);
+
+        if let Some(ttl) = self.config.message_ttl {
+            self.inner.expire_sync(key, ttl.as_secs());
+        }
+
+        Ok(())
+    }
+
+    /// Updates the content hash of a recent message from a MessageUpdate
+    /// event.
+    ///
+    /// The message is found and updated atomically, so concurrent pushes and
+    /// deletions can't shift it out from under the update.
+    ///
+    /// Returns whether the message was in the channel's recent message list.
+    pub async fn update_message<'a>(
+        &'a self,
+        event: &'a MessageUpdateEvent,
+    ) -> Result<bool> {
+        let content = match event.content.as_ref() {
+            Some(content) => content,
+            None => return Ok(false),
+        };
+
+        let found: i64 = await!(self.inner.eval(
+            UPDATE_MESSAGE_SCRIPT,
+            vec![gen::channel_messages(event.channel_id.0)],
+            vec![
+                RespValue::from(event.id.0.to_string()),
+                RespValue::from(CachedMessage::hash_content(content).to_string()),
+            ],
+        ))?;
+
+        Ok(found == 1)
+    }
+
+    /// Removes a message from its channel's recent message list.
+    ///
+    /// Returns whether the message was in the list.
+    pub async fn delete_message(
+        &self,
+        channel_id: u64,
+        message_id: u64,
+    ) -> Result<bool> {
+        let count = await!(self.delete_messages(channel_id, vec![message_id]))?;
+
+        Ok(count > 0)
+    }
+
+    /// Removes messages from a channel's recent message list, such as from a
+    /// MessageDeleteBulk event.
+    ///
+    /// Returns the number of messages removed.
+    pub async fn delete_messages(
+        &self,
+        channel_id: u64,
+        message_ids: Vec<u64>,
+    ) -> Result<u64> {
+        if message_ids.is_empty() {
+            return Ok(0);
+        }
+
+        let count: i64 = await!(self.inner.eval(
+            DELETE_MESSAGES_SCRIPT,
+            vec![gen::channel_messages(channel_id)],
+            message_ids.iter().map(|id| RespValue::from(id.to_string())).collect(),
+        ))?;
+
+        Ok(count as u64)
+    }
+
+    async fn get_message_entries(
+        &self,
+        channel_id: u64,
+        max: i64,
+    ) -> Result<Vec<Vec<u8>>> {
+        let resp = await!(self.inner.lrange(
+            gen::channel_messages(channel_id),
+            0,
+            max,
+        ))?;
+
+        FromResp::from_resp(resp).into_err()
+    }
+}
+
+/// Guild settings.
+impl Cache {
+    /// Gets a guild's settings, with defaults for anything the guild hasn't
+    /// configured.
+    pub async fn get_guild_settings(
+        &self,
+        guild_id: u64,
+    ) -> Result<GuildSettings> {
+        let values = await!(self.inner.hgetall(gen::guild_settings(guild_id)))?;
+
+        FromResp::from_resp(values).into_err()
+    }
+
+    /// Updates some of a guild's settings.
+    pub async fn patch_guild_settings(
+        &self,
+        guild_id: u64,
+        patch: GuildSettingsPatch,
+    ) -> Result<()> {
+        let key = gen::guild_settings(guild_id);
+        let changes = patch.into_changes();
+
+        if !changes.set.is_empty() {
+            await!(self.inner.hmset(key.clone(), changes.set))?;
+        }
+
+        if !changes.delete.is_empty() {
+            await!(self.inner.hdel(key, changes.delete))?;
+        }
+
+        Ok(())
+    }
+
+    /// Resets all of a guild's settings to their defaults.
+    pub async fn reset_guild_settings(&self, guild_id: u64) -> Result<()> {
+        await!(self.inner.del(gen::guild_settings(guild_id)))
+    }
+}
+
+/// Recently played history.
+impl Cache {
+    /// Gets a page of a guild's recently played tracks, most recent first.
+    ///
+    /// Pages are zero-indexed.
+    pub async fn get_history(
+        &self,
+        guild_id: u64,
+        page: u64,
+        per_page: u64,
+    ) -> Result<Vec<HistoryEntry>> {
+        if per_page == 0 {
+            return Ok(vec![]);
+        }
+
+        let start = (page * per_page) as i64;
+        let resp = await!(self.inner.lrange(
+            gen::history(guild_id),
+            start,
+            start + per_page as i64 - 1,
+        ))?;
+        let entries: Vec<Vec<u8>> = FromResp::from_resp(resp).into_err()?;
+
+        let mut history = Vec::with_capacity(entries.len());
+
+        for entry in entries {
+            history.push(serde_json::from_slice(&entry)?);
+        }
+
+        Ok(history)
+    }
+
+    /// Gets the number of tracks in a guild's history.
+    pub async fn get_history_len(&self, guild_id: u64) -> Result<u64> {
+        let len = await!(self.inner.send::<i64>(resp_array![
+            "LLEN",
+            gen::history(guild_id)
+        ]))?;
+
+        Ok(len as u64)
+    }
+
+    /// Pushes a finished track to a guild's history.
+    ///
+    /// The history is trimmed to the configured
+    /// [`history_size`][`Config::history_size`].
+    ///
+    /// [`Config::history_size`]: struct.Config.html#structfield.history_size
+    pub fn push_history<'a>(
+        &'a self,
+        guild_id: u64,
+        entry: &'a HistoryEntry,
+    ) -> Result<()> {
+        let size = self.config.history_size;
+
+        if size == 0 {
+            return Ok(());
+        }
+
+        let key = gen::history(guild_id);
+
+        self.inner.lpush_sync(key.clone(), vec![serde_json::to_vec(entry)?]);
+        self.inner.ltrim_sync(key, 0, size as i64 - 1);
+
+        Ok(())
+    }
+
+    /// Moves a track from a guild's history back into its queue.
+    ///
+    /// The index is the position in the history, where 0 is the most recently
+    /// played track.
+    ///
+    /// Returns the entry that was requeued, if one was at the index.
+    pub async fn requeue_from_history(
+        &self,
+        guild_id: u64,
+        index: u64,
+    ) -> Result<Option<HistoryEntry>> {
+        let bytes: Option<Vec<u8>> = await!(self.inner.eval(
+            REQUEUE_SCRIPT,
+            vec![gen::history(guild_id), gen::queue(guild_id)],
+            vec![RespValue::from(index as usize)],
+        ))?;
+
+        match bytes {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Deletes a guild's history.
+    pub async fn delete_history(&self, guild_id: u64) -> Result<()> {
+        await!(self.inner.del(gen::history(guild_id)))
+    }
+}
@@ -1,29 +1,315 @@
-use crate::error::Result;
+use crate::{
+    config::RetryPolicy,
+    error::{Error, Result},
+    resp_impl::RespValueExt,
+    writes::{self, WriteErrorHandler, WriteTracker},
+};
 use essentials::result::ResultExt;
-use futures::compat::Future01CompatExt;
+use futures::{
+    compat::Future01CompatExt,
+    future,
+    stream::{self, Stream},
+};
+use futures_timer::{Delay, FutureExt as _};
 use redis_async::{
     client::PairedConnection,
+    error::Error as RedisError,
     resp::{FromResp, RespValue},
 };
-use std::sync::Arc;
+use std::{
+    collections::VecDeque,
+    io::ErrorKind,
+    sync::Arc,
+    time::Duration,
+};
+use tokio_executor::Executor;
+
+/// Commands that only read data, and so are safe to retry.
+const READ_COMMANDS: &[&str] = &[
+    "EXISTS",
+    "GET",
+    "HGET",
+    "HGETALL",
+    "HSCAN",
+    "LINDEX",
+    "LLEN",
+    "LRANGE",
+    "MGET",
+    "SCAN",
+    "SCARD",
+    "SINTER",
+    "SISMEMBER",
+    "SMEMBERS",
+    "SSCAN",
+    "ZRANGEBYLEX",
+];
+
+/// Whether a command only reads data.
+fn is_read(command: &RespValue) -> bool {
+    let name = match command {
+        RespValue::Array(values) => match values.first() {
+            Some(RespValue::BulkString(name)) => name,
+            _ => return false,
+        },
+        _ => return false,
+    };
+
+    READ_COMMANDS.iter().any(|read| read.as_bytes().eq_ignore_ascii_case(name))
+}
+
+/// Whether an error may not happen again if the command is retried.
+///
+/// Internal errors aren't, since redis-async uses them for a paired
+/// connection that has closed, which never reconnects.
+fn is_transient(error: &Error) -> bool {
+    match error {
+        Error::Timeout | Error::Redis(RedisError::IO(_)) => true,
+        _ => false,
+    }
+}
+
+/// The state of a cursor-based iteration over a SCAN family command.
+struct Scan<T> {
+    /// The cursor to send next, or `None` once the iteration is complete.
+    cursor: Option<String>,
+    inner: CommandablePairedConnection,
+    items: VecDeque<T>,
+    /// Creates the command to send for a cursor.
+    request: Box<dyn Fn(&str) -> RespValue + Send>,
+    /// Converts the elements of a reply into items.
+    split: fn(Vec<RespValue>) -> Vec<T>,
+}
+
+impl<T> Scan<T> {
+    fn new(
+        inner: CommandablePairedConnection,
+        request: Box<dyn Fn(&str) -> RespValue + Send>,
+        split: fn(Vec<RespValue>) -> Vec<T>,
+    ) -> Self {
+        Self {
+            cursor: Some("0".to_owned()),
+            inner,
+            items: VecDeque::new(),
+            request,
+            split,
+        }
+    }
+
+    fn into_stream(self) -> impl Stream<Item = Result<T>> {
+        stream::unfold(self, |mut state| async move {
+            loop {
+                if let Some(item) = state.items.pop_front() {
+                    return Some((Ok(item), state));
+                }
+
+                // Taking the cursor means that the stream ends after an error.
+                let cursor = state.cursor.take()?;
+                let request = (state.request)(&cursor);
+
+                let reply = match await!(state.inner.send::<RespValue>(request)) {
+                    Ok(reply) => reply,
+                    Err(why) => return Some((Err(why), state)),
+                };
+
+                let (next, elements) = match parse_scan_reply(reply) {
+                    Ok(reply) => reply,
+                    Err(why) => return Some((Err(why), state)),
+                };
+
+                if next != "0" {
+                    state.cursor = Some(next);
+                }
+
+                state.items.extend((state.split)(elements));
+            }
+        })
+    }
+}
 
+/// Splits a SCAN family reply into the next cursor and the elements.
+fn parse_scan_reply(reply: RespValue) -> Result<(String, Vec<RespValue>)> {
+    let mut reply = match reply {
+        RespValue::Array(values) => values.into_iter(),
+        _ => return Err(Error::None),
+    };
+
+    match (reply.next(), reply.next()) {
+        (Some(RespValue::BulkString(next)), Some(RespValue::Array(elements))) => {
+            Ok((String::from_utf8(next).ok()?, elements))
+        },
+        _ => Err(Error::None),
+    }
+}
+
+fn split_strings(elements: Vec<RespValue>) -> Vec<String> {
+    elements.into_iter().map(RespValueExt::into_string).collect()
+}
+
+fn split_pairs(elements: Vec<RespValue>) -> Vec<(String, RespValue)> {
+    let mut pairs = Vec::with_capacity(elements.len() / 2);
+    let mut elements = elements.into_iter();
+
+    while let (Some(field), Some(value)) = (elements.next(), elements.next()) {
+        pairs.push((field.into_string(), value));
+    }
+
+    pairs
+}
+
+#[derive(Clone)]
 pub struct CommandablePairedConnection {
     inner: Arc<PairedConnection>,
+    retry: RetryPolicy,
+    timeout: Option<Duration>,
+    writes: WriteTracker,
 }
 
 impl CommandablePairedConnection {
+    /// Creates a connection whose commands never time out and aren't retried.
+    ///
+    /// Failed fire-and-forget writes are only logged.
     pub fn new(connection: Arc<PairedConnection>) -> Self {
+        Self::with_policy(connection, None, RetryPolicy::none(), None)
+    }
+
+    /// Creates a connection whose commands time out after `timeout`, with
+    /// failed reads retried according to `retry`.
+    ///
+    /// Failed fire-and-forget writes are logged and passed to
+    /// `on_write_error`, if given.
+    pub fn with_policy(
+        connection: Arc<PairedConnection>,
+        timeout: Option<Duration>,
+        retry: RetryPolicy,
+        on_write_error: Option<WriteErrorHandler>,
+    ) -> Self {
         Self {
             inner: connection,
+            retry,
+            timeout,
+            writes: WriteTracker::new(on_write_error),
+        }
+    }
+
+    /// Spawns the task that waits on the replies to fire-and-forget writes
+    /// onto the given executor, instead of the one current when the first
+    /// write is sent.
+    pub(crate) fn start_write_tracker(&self, executor: &mut impl Executor) -> Result<()> {
+        self.writes.start_on(executor)
+    }
+
+    /// Returns a handle to the same connection whose commands time out after
+    /// the given duration instead.
+    pub fn with_timeout(&self, timeout: Option<Duration>) -> Self {
+        Self {
+            timeout,
+            ..self.clone()
         }
     }
 
+    /// Sends a command and waits for its reply.
+    ///
+    /// Fails with [`Error::Timeout`] if the connection's timeout passes
+    /// without a reply. Reads that time out or fail with an I/O error are
+    /// retried according to the connection's [`RetryPolicy`]; other
+    /// commands, such as list pushes, are never retried since they could be
+    /// applied twice.
+    ///
+    /// Dropping the returned future stops waiting for the reply, although
+    /// Redis may still run the command.
+    ///
+    /// [`Error::Timeout`]: ../enum.Error.html#variant.Timeout
+    /// [`RetryPolicy`]: ../struct.RetryPolicy.html
     pub async fn send<T: FromResp>(&self, value: RespValue) -> Result<T> {
-        await!(self.inner.send(value).compat()).into_err()
+        let retries = if is_read(&value) {
+            self.retry.max_retries
+        } else {
+            0
+        };
+        let mut attempt = 0;
+
+        loop {
+            let why = match await!(self.send_once(value.clone())) {
+                Ok(reply) => return Ok(reply),
+                Err(why) => why,
+            };
+
+            if attempt >= retries || !is_transient(&why) {
+                return Err(why);
+            }
+
+            debug!("Retrying read after error: {:?}", why);
+            await!(Delay::new(self.retry.backoff(attempt)).compat())?;
+            attempt += 1;
+        }
+    }
+
+    async fn send_once<T: FromResp>(&self, value: RespValue) -> Result<T> {
+        let reply = self.inner.send(value);
+
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return await!(reply.compat()).into_err(),
+        };
+
+        // The reply itself never fails with an I/O error, since those happen
+        // on the connection's own task, so a timed out error can only be
+        // from the timer.
+        match await!(reply.timeout(timeout).compat()) {
+            Ok(reply) => Ok(reply),
+            Err(RedisError::IO(ref why)) if why.kind() == ErrorKind::TimedOut => {
+                Err(Error::Timeout)
+            },
+            Err(why) => Err(why.into()),
+        }
+    }
+
+    /// Sends multiple commands at once and waits for all of their replies,
+    /// rather than waiting for each reply before sending the next command.
+    ///
+    /// Replies are returned in the same order as the commands.
+    pub async fn send_pipelined<T: FromResp>(
+        &self,
+        values: Vec<RespValue>,
+    ) -> Result<Vec<T>> {
+        let requests = values
+            .into_iter()
+            .map(|value| Box::pin(self.send(value)))
+            .collect::<Vec<_>>();
+
+        await!(future::join_all(requests)).into_iter().collect()
     }
 
+    /// Sends a command without waiting for its reply.
+    ///
+    /// The reply is waited on in the background. If the command fails, the
+    /// failure is logged, passed to the connection's [`WriteErrorHandler`],
+    /// and returned by the next [`flush`].
+    ///
+    /// [`WriteErrorHandler`]: ../struct.WriteErrorHandler.html
+    /// [`flush`]: #method.flush
     pub fn send_sync(&self, value: RespValue) {
-        self.inner.send_and_forget(value)
+        let command = writes::command_name(&value);
+        let reply = self.inner.send::<RespValue>(value);
+
+        let reply: writes::Reply = match self.timeout {
+            Some(timeout) => Box::pin(reply.timeout(timeout).compat()),
+            None => Box::pin(reply.compat()),
+        };
+
+        self.writes.track(command, reply);
+    }
+
+    /// Waits for the replies to all commands sent so far by [`send_sync`], and
+    /// returns the first failure among them since the last flush.
+    ///
+    /// Failures are still passed to the connection's [`WriteErrorHandler`]
+    /// as they happen.
+    ///
+    /// [`WriteErrorHandler`]: ../struct.WriteErrorHandler.html
+    /// [`send_sync`]: #method.send_sync
+    pub async fn flush(&self) -> Result<()> {
+        await!(self.writes.flush())
     }
 
     pub async fn del(&self, key: String) -> Result<()> {
@@ -56,6 +342,46 @@ impl CommandablePairedConnection {
         }
     }
 
+    pub async fn eval<'a, T: FromResp + 'static>(
+        &'a self,
+        script: &'a str,
+        keys: Vec<String>,
+        mut args: Vec<RespValue>,
+    ) -> Result<T> {
+        let mut values = vec![
+            RespValue::from(keys.len()),
+        ];
+        values.extend(keys.into_iter().map(RespValue::from));
+        values.append(&mut args);
+
+        await!(self.send(resp_array!["EVAL", script].append(&mut values)))
+    }
+
+    pub fn eval_sync(
+        &self,
+        script: &str,
+        keys: Vec<String>,
+        mut args: Vec<RespValue>,
+    ) {
+        let mut values = vec![
+            RespValue::from(keys.len()),
+        ];
+        values.extend(keys.into_iter().map(RespValue::from));
+        values.append(&mut args);
+
+        self.send_sync(resp_array!["EVAL", script].append(&mut values));
+    }
+
+    pub async fn exists(&self, key: String) -> Result<bool> {
+        let count = await!(self.send::<i64>(resp_array!["EXISTS", key]))?;
+
+        Ok(count > 0)
+    }
+
+    pub fn expire_sync(&self, key: String, seconds: u64) {
+        self.send_sync(resp_array!["EXPIRE", key, seconds as usize]);
+    }
+
     pub async fn get<T: FromResp + 'static>(
         &self,
         key: String,
@@ -104,6 +430,15 @@ impl CommandablePairedConnection {
         await!(self.send(resp_array!["HGETALL", key]))
     }
 
+    pub async fn hgetall_pipelined(
+        &self,
+        keys: Vec<String>,
+    ) -> Result<Vec<RespValue>> {
+        await!(self.send_pipelined(keys.into_iter().map(|key| {
+            resp_array!["HGETALL", key]
+        }).collect()))
+    }
+
     pub async fn hmset<'a, T: Into<RespValue>, It: IntoIterator<Item = T> + 'a>(
         &'a self,
         key: String,
@@ -138,6 +473,50 @@ impl CommandablePairedConnection {
         Ok(())
     }
 
+    pub async fn lindex<T: FromResp + 'static>(
+        &self,
+        key: String,
+        index: i64,
+    ) -> Result<T> {
+        let value = await!(self.send(resp_array!["LINDEX", key, index]))?;
+
+        FromResp::from_resp(value).into_err()
+    }
+
+    pub async fn lrem<T: Into<RespValue>>(
+        &self,
+        key: String,
+        count: i64,
+        value: T,
+    ) -> Result<i64> {
+        await!(self.send::<i64>(resp_array!["LREM", key, count].append(&mut vec![value.into()])))
+    }
+
+    pub async fn lset<T: Into<RespValue>>(
+        &self,
+        key: String,
+        index: i64,
+        value: T,
+    ) -> Result<()> {
+        await!(self.send::<RespValue>(resp_array!["LSET", key, index].append(&mut vec![value.into()])))?;
+
+        Ok(())
+    }
+
+    pub fn ltrim_sync(&self, key: String, start: i64, stop: i64) {
+        self.send_sync(resp_array!["LTRIM", key, start, stop])
+    }
+
+    pub fn lpush_sync<'a, T: Into<RespValue>, It: IntoIterator<Item = T> + 'a>(
+        &'a self,
+        key: String,
+        values: It,
+    ) {
+        let mut values = values.into_iter().map(Into::into).collect();
+
+        self.send_sync(resp_array!["LPUSH", key].append(&mut values));
+    }
+
     pub async fn rpush<'a, T: Into<RespValue>, It: IntoIterator<Item = T> + 'a>(
         &'a self,
         key: String,
@@ -194,6 +573,12 @@ impl CommandablePairedConnection {
         ].append(&mut values));
     }
 
+    pub async fn scard(&self, key: String) -> Result<u64> {
+        let count = await!(self.send::<i64>(resp_array!["SCARD", key]))?;
+
+        Ok(count as u64)
+    }
+
     pub async fn set<'a, T: Into<RespValue>, It: IntoIterator<Item = T> + 'a>(
         &'a self,
         key: String,
@@ -206,6 +591,17 @@ impl CommandablePairedConnection {
         Ok(())
     }
 
+    pub async fn sinter<T: FromResp + 'static>(
+        &self,
+        keys: Vec<String>,
+    ) -> Result<T> {
+        let mut keys = keys.into_iter().map(Into::into).collect();
+
+        let values = await!(self.send(resp_array!["SINTER"].append(&mut keys)))?;
+
+        FromResp::from_resp(values).into_err()
+    }
+
     pub async fn smembers<T: FromResp + 'static>(
         &self,
         key: String,
@@ -215,6 +611,48 @@ impl CommandablePairedConnection {
         FromResp::from_resp(values).into_err()
     }
 
+    /// Iterates over the keys matching a glob-style pattern using SCAN.
+    ///
+    /// `count` is a hint for how many keys Redis should check per call.
+    /// Keys may be returned more than once.
+    pub fn scan(
+        &self,
+        pattern: String,
+        count: u64,
+    ) -> impl Stream<Item = Result<String>> {
+        Scan::new(self.clone(), Box::new(move |cursor| {
+            resp_array!["SCAN", cursor, "MATCH", pattern.clone(), "COUNT", count as usize]
+        }), split_strings).into_stream()
+    }
+
+    /// Iterates over the members of a Set using SSCAN.
+    ///
+    /// `count` is a hint for how many members Redis should return per call.
+    /// Members may be returned more than once.
+    pub fn sscan(
+        &self,
+        key: String,
+        count: u64,
+    ) -> impl Stream<Item = Result<String>> {
+        Scan::new(self.clone(), Box::new(move |cursor| {
+            resp_array!["SSCAN", key.clone(), cursor, "COUNT", count as usize]
+        }), split_strings).into_stream()
+    }
+
+    /// Iterates over the fields and values of a hash using HSCAN.
+    ///
+    /// `count` is a hint for how many fields Redis should return per call.
+    /// Fields may be returned more than once.
+    pub fn hscan(
+        &self,
+        key: String,
+        count: u64,
+    ) -> impl Stream<Item = Result<(String, RespValue)>> {
+        Scan::new(self.clone(), Box::new(move |cursor| {
+            resp_array!["HSCAN", key.clone(), cursor, "COUNT", count as usize]
+        }), split_pairs).into_stream()
+    }
+
     pub async fn srem(&self, key: String, mut ids: Vec<usize>) -> Result<RespValue> {
         await!(self.send(resp_array!["SREM", key].append(&mut ids)))
     }
@@ -231,3 +669,76 @@ impl CommandablePairedConnection {
         self.send_sync(resp_array!["LRANGE", key, min, max])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use redis_async::{error::Error as RedisError, resp::RespValue};
+
+    #[test]
+    fn test_is_read() {
+        assert!(super::is_read(&resp_array!["HGETALL", "g:1"]));
+        assert!(super::is_read(&resp_array!["smembers", "g:1:m"]));
+        assert!(!super::is_read(&resp_array!["LPUSH", "c:1", "a"]));
+        assert!(!super::is_read(&resp_array!["EVAL", "return 1", 0usize]));
+        assert!(!super::is_read(&RespValue::Nil));
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(super::is_transient(&Error::Timeout));
+        assert!(!super::is_transient(&Error::None));
+        assert!(!super::is_transient(&Error::Redis(RedisError::Internal(
+            "Connection closed".to_owned(),
+        ))));
+    }
+
+    #[test]
+    fn test_parse_scan_reply() {
+        let reply = RespValue::Array(vec![
+            RespValue::BulkString(b"17".to_vec()),
+            RespValue::Array(vec![RespValue::BulkString(b"g:1".to_vec())]),
+        ]);
+
+        let (next, elements) = super::parse_scan_reply(reply).unwrap();
+        assert_eq!(next, "17");
+        assert_eq!(elements, vec![RespValue::BulkString(b"g:1".to_vec())]);
+    }
+
+    #[test]
+    fn test_parse_scan_reply_malformed() {
+        assert!(super::parse_scan_reply(RespValue::Nil).is_err());
+        assert!(super::parse_scan_reply(RespValue::Array(vec![
+            RespValue::BulkString(b"0".to_vec()),
+        ])).is_err());
+        assert!(super::parse_scan_reply(RespValue::Array(vec![
+            RespValue::Integer(0),
+            RespValue::Array(vec![]),
+        ])).is_err());
+    }
+
+    #[test]
+    fn test_split_strings() {
+        let elements = vec![
+            RespValue::BulkString(b"g:1".to_vec()),
+            RespValue::BulkString(b"g:2".to_vec()),
+        ];
+
+        assert_eq!(super::split_strings(elements), vec!["g:1", "g:2"]);
+    }
+
+    #[test]
+    fn test_split_pairs() {
+        let elements = vec![
+            RespValue::BulkString(b"name".to_vec()),
+            RespValue::BulkString(b"test".to_vec()),
+            RespValue::BulkString(b"permissions".to_vec()),
+            RespValue::BulkString(b"8".to_vec()),
+        ];
+
+        assert_eq!(super::split_pairs(elements), vec![
+            ("name".to_owned(), RespValue::BulkString(b"test".to_vec())),
+            ("permissions".to_owned(), RespValue::BulkString(b"8".to_vec())),
+        ]);
+    }
+}
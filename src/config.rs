@@ -0,0 +1,136 @@
+use crate::writes::WriteErrorHandler;
+use std::{num::NonZeroU64, time::Duration};
+
+/// How failed reads are retried.
+///
+/// Only commands that read data are retried, as retrying a write such as a
+/// list push could apply it twice. Reads are retried when they time out or
+/// fail with an I/O error, but not when Redis replies with an error or the
+/// connection has closed, since a closed connection doesn't reconnect.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RetryPolicy {
+    /// The maximum number of retries after the first attempt.
+    ///
+    /// Defaults to 2.
+    pub max_retries: u32,
+    /// How long to wait before the first retry. The wait doubles with each
+    /// retry after that.
+    ///
+    /// Defaults to 50 milliseconds.
+    pub initial_backoff: Duration,
+    /// The longest to wait between retries.
+    ///
+    /// Defaults to 1 second.
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// How long to wait before the given retry, counting from 0.
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .checked_mul(1 << retry.min(31))
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Configuration for a [`Cache`].
+///
+/// [`Cache`]: struct.Cache.html
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// How long to wait for a command's reply before failing with
+    /// [`Error::Timeout`].
+    ///
+    /// Defaults to 5 seconds. `None` waits indefinitely.
+    ///
+    /// [`Error::Timeout`]: enum.Error.html#variant.Timeout
+    pub command_timeout: Option<Duration>,
+    /// The number of recently played tracks to keep per guild.
+    ///
+    /// Defaults to 100.
+    pub history_size: usize,
+    /// How long after its last heartbeat a Lavalink node is considered
+    /// down, after which its guilds are reassigned to other nodes.
+    ///
+    /// Defaults to 30 seconds.
+    pub lavalink_heartbeat_timeout: Duration,
+    /// The number of recent messages to keep per channel.
+    ///
+    /// Defaults to 50.
+    pub message_cache_size: usize,
+    /// How long a channel's recent messages are kept after the last message
+    /// in it is cached.
+    ///
+    /// Defaults to 1 day. `None` keeps them indefinitely.
+    pub message_ttl: Option<Duration>,
+    /// Receives the failures of writes that don't wait for their replies,
+    /// such as presence updates.
+    ///
+    /// Failures are logged either way. Defaults to `None`.
+    pub on_write_error: Option<WriteErrorHandler>,
+    /// How failed reads are retried.
+    ///
+    /// Defaults to [`RetryPolicy::default`].
+    ///
+    /// [`RetryPolicy::default`]: struct.RetryPolicy.html#impl-Default
+    pub retry: RetryPolicy,
+    /// The total number of shards, used to index guilds by shard.
+    ///
+    /// Defaults to `None`, in which case guilds aren't indexed by shard.
+    pub shard_count: Option<NonZeroU64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            command_timeout: Some(Duration::from_secs(5)),
+            history_size: 100,
+            lavalink_heartbeat_timeout: Duration::from_secs(30),
+            message_cache_size: 50,
+            message_ttl: Some(Duration::from_secs(60 * 60 * 24)),
+            on_write_error: None,
+            retry: RetryPolicy::default(),
+            shard_count: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::RetryPolicy;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::default();
+
+        assert_eq!(policy.backoff(0), Duration::from_millis(50));
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(4), Duration::from_millis(800));
+        assert_eq!(policy.backoff(5), Duration::from_secs(1));
+        assert_eq!(policy.backoff(40), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_none() {
+        assert_eq!(RetryPolicy::none().max_retries, 0);
+    }
+}
@@ -0,0 +1,390 @@
+//! A serde deserializer over RESP values, driven by the type being decoded.
+//!
+//! Redis replies don't carry types: hash values are all bulk strings, so a
+//! name like `"0123456"` can't be told apart from a number by looking at it.
+//! Instead, each value is decoded as whatever the target field asks for, so
+//! integers are parsed only when the field is an integer and strings are kept
+//! exactly as they were written.
+//!
+//! Hashes are read as arrays of alternating fields and values, which are
+//! decoded as maps or structs. Arrays requested as sequences, such as Set
+//! members, are decoded element by element.
+
+use redis_async::{
+    error::Error as RedisError,
+    resp::RespValue,
+};
+use serde::de::{
+    self,
+    Deserialize,
+    DeserializeSeed,
+    IntoDeserializer,
+    MapAccess,
+    SeqAccess,
+    Visitor,
+};
+use std::{
+    error::Error as StdError,
+    fmt::{Display, Formatter, Result as FmtResult},
+    str::FromStr,
+    vec::IntoIter,
+};
+
+/// An error decoding a RESP value.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Error(String);
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str(&self.0)
+    }
+}
+
+impl StdError for Error {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+/// Decodes a RESP value, such as an HGETALL reply, into a type.
+pub fn from_resp<'de, T: Deserialize<'de>>(resp: RespValue) -> Result<T, RedisError> {
+    T::deserialize(Deserializer(resp)).map_err(|why| {
+        RedisError::Unexpected(format!("Couldn't deserialize a cached value: err={}", why))
+    })
+}
+
+/// A deserializer for a single RESP value.
+pub struct Deserializer(RespValue);
+
+impl Deserializer {
+    fn text(self) -> Result<String, Error> {
+        match self.0 {
+            RespValue::BulkString(bytes) => String::from_utf8(bytes).map_err(|why| {
+                Error(format!("Invalid UTF-8: {}", why))
+            }),
+            RespValue::SimpleString(string) => Ok(string),
+            RespValue::Integer(integer) => Ok(integer.to_string()),
+            other => Err(Error(format!("Expected a string: {:?}", other))),
+        }
+    }
+
+    fn parse<T: FromStr>(self) -> Result<T, Error> where T::Err: Display {
+        let text = self.text()?;
+
+        text.parse().map_err(|why| Error(format!("Invalid number {:?}: {}", text, why)))
+    }
+}
+
+// This is a `macro_rules!` macro rather than a `macro` so that the `'de`
+// lifetime of the impl it expands in is visible to it.
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)+) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                visitor.$visit(self.parse()?)
+            }
+        )+
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            RespValue::Array(values) => visitor.visit_seq(Seq(values.into_iter())),
+            RespValue::BulkString(bytes) => match String::from_utf8(bytes) {
+                Ok(string) => visitor.visit_string(string),
+                Err(why) => visitor.visit_byte_buf(why.into_bytes()),
+            },
+            RespValue::Error(why) => Err(Error(format!("Redis error: {}", why))),
+            RespValue::Integer(integer) => visitor.visit_i64(integer),
+            RespValue::Nil => visitor.visit_none(),
+            RespValue::SimpleString(string) => visitor.visit_string(string),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match &*self.text()? {
+            "1" | "true" => visitor.visit_bool(true),
+            "0" | "false" => visitor.visit_bool(false),
+            other => Err(Error(format!("Invalid bool: {:?}", other))),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_string(self.text()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_string(self.text()?)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            RespValue::BulkString(bytes) => visitor.visit_byte_buf(bytes),
+            other => Deserializer(other).deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            RespValue::Nil => visitor.visit_none(),
+            other => visitor.visit_some(Deserializer(other)),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            RespValue::Array(values) => visitor.visit_seq(Seq(values.into_iter())),
+            other => Err(Error(format!("Expected an array: {:?}", other))),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            RespValue::Array(values) => visitor.visit_map(Pairs {
+                iter: values.into_iter(),
+                value: None,
+            }),
+            other => Err(Error(format!("Expected a hash: {:?}", other))),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_enum(self.text()?.into_deserializer())
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+}
+
+/// The elements of an array.
+struct Seq(IntoIter<RespValue>);
+
+impl<'de> SeqAccess<'de> for Seq {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        match self.0.next() {
+            Some(value) => seed.deserialize(Deserializer(value)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+/// The alternating fields and values of a hash.
+struct Pairs {
+    iter: IntoIter<RespValue>,
+    value: Option<RespValue>,
+}
+
+impl<'de> MapAccess<'de> for Pairs {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        let field = match self.iter.next() {
+            Some(field) => field,
+            None => return Ok(None),
+        };
+
+        self.value = Some(self.iter.next().ok_or_else(|| {
+            Error("Hash field has no value".to_owned())
+        })?);
+
+        seed.deserialize(Deserializer(field)).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Error> {
+        let value = self.value.take().ok_or_else(|| {
+            Error("Value requested before field".to_owned())
+        })?;
+
+        seed.deserialize(Deserializer(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len() / 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use redis_async::resp::RespValue;
+    use std::collections::HashSet;
+    use super::from_resp;
+
+    fn bulk(value: &str) -> RespValue {
+        RespValue::BulkString(value.as_bytes().to_vec())
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Inner {
+        id: u64,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Outer {
+        flag: bool,
+        ids: HashSet<u64>,
+        inner: Inner,
+        name: String,
+        #[serde(default)]
+        nick: Option<String>,
+        small: u16,
+    }
+
+    #[test]
+    fn test_struct() {
+        let value = RespValue::Array(vec![
+            bulk("flag"),
+            bulk("1"),
+            bulk("ids"),
+            RespValue::Array(vec![bulk("1"), bulk("2")]),
+            bulk("inner"),
+            RespValue::Array(vec![bulk("id"), bulk("381880193251409931")]),
+            bulk("name"),
+            bulk("0123456"),
+            bulk("small"),
+            bulk("0001"),
+        ]);
+
+        let outer: Outer = from_resp(value).unwrap();
+        assert!(outer.flag);
+        assert_eq!(outer.ids, vec![1, 2].into_iter().collect());
+        assert_eq!(outer.inner, Inner { id: 381880193251409931 });
+        assert_eq!(outer.name, "0123456");
+        assert_eq!(outer.nick, None);
+        assert_eq!(outer.small, 1);
+    }
+
+    #[test]
+    fn test_strings_stay_strings() {
+        let name: String = from_resp(bulk("11111111111111111111111111111111")).unwrap();
+        assert_eq!(name, "11111111111111111111111111111111");
+
+        let nick: Option<String> = from_resp(bulk("007")).unwrap();
+        assert_eq!(nick.as_ref().map(AsRef::as_ref), Some("007"));
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(from_resp::<u64>(bulk("18446744073709551615")).unwrap(), u64::max_value());
+        assert_eq!(from_resp::<i64>(RespValue::Integer(-2)).unwrap(), -2);
+        assert!(from_resp::<u64>(bulk("abc")).is_err());
+        assert!(from_resp::<u8>(bulk("256")).is_err());
+    }
+
+    #[test]
+    fn test_bool() {
+        assert!(from_resp::<bool>(bulk("true")).unwrap());
+        assert!(!from_resp::<bool>(bulk("0")).unwrap());
+        assert!(from_resp::<bool>(bulk("2")).is_err());
+    }
+
+    #[test]
+    fn test_option() {
+        assert_eq!(from_resp::<Option<u64>>(RespValue::Nil).unwrap(), None);
+        assert_eq!(from_resp::<Option<u64>>(bulk("5")).unwrap(), Some(5));
+    }
+
+    #[test]
+    fn test_odd_hash() {
+        let value = RespValue::Array(vec![bulk("id")]);
+
+        assert!(from_resp::<Inner>(value).is_err());
+    }
+}
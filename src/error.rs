@@ -1,22 +1,38 @@
+use crate::writes::WriteError;
 use redis_async::error::Error as RedisError;
 use serde_json::Error as JsonError;
 use std::{
     error::Error as StdError,
     fmt::{Display, Formatter, Result as FmtResult},
+    io::Error as IoError,
     num::ParseIntError,
     option::NoneError,
     result::Result as StdResult,
 };
+use tokio_executor::SpawnError;
 
 pub type Result<T> = StdResult<T, Error>;
 
 #[derive(Debug)]
 pub enum Error {
+    InvalidKey,
     InvalidLoopMode,
+    Io(IoError),
     Json(JsonError),
     None,
     ParseInt(ParseIntError),
     Redis(RedisError),
+    /// The task tracking fire-and-forget writes couldn't be spawned.
+    Spawn(SpawnError),
+    /// A command didn't get a reply within the configured timeout.
+    Timeout,
+    /// A fire-and-forget write failed, as reported by a flush.
+    Write(WriteError),
+    /// The task tracking fire-and-forget writes stopped, which only happens
+    /// if its executor shut down or a [`WriteErrorHandler`] panicked.
+    ///
+    /// [`WriteErrorHandler`]: struct.WriteErrorHandler.html
+    WriteTrackerStopped,
 }
 
 impl Display for Error {
@@ -30,15 +46,27 @@ impl StdError for Error {
         use self::Error::*;
 
         match self {
+            InvalidKey => "Invalid key",
             InvalidLoopMode => "Invalid loop mode",
+            Io(why) => why.description(),
             Json(why) => why.description(),
             None => "none",
             ParseInt(why) => why.description(),
             Redis(why) => why.description(),
+            Spawn(why) => why.description(),
+            Timeout => "Timed out waiting for a reply",
+            Write(_) => "A fire-and-forget write failed",
+            WriteTrackerStopped => "The write tracker stopped",
         }
     }
 }
 
+impl From<IoError> for Error {
+    fn from(e: IoError) -> Error {
+        Error::Io(e)
+    }
+}
+
 impl From<JsonError> for Error {
     fn from(e: JsonError) -> Error {
         Error::Json(e)
@@ -62,3 +90,9 @@ impl From<RedisError> for Error {
         Error::Redis(e)
     }
 }
+
+impl From<SpawnError> for Error {
+    fn from(e: SpawnError) -> Error {
+        Error::Spawn(e)
+    }
+}
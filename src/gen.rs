@@ -1,78 +1,417 @@
+use crate::error::{Error, Result};
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    str::FromStr,
+};
+
+/// A key in the cache.
+///
+/// This can be displayed as the key's string form, and parsed back from one,
+/// which is useful for keyspace notifications and SCAN results.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Key {
+    Channel(u64),
+    ChannelListeners(u64),
+    ChannelMessages(u64),
+    ChannelVoiceStates(u64),
+    Choice(u64),
+    Emoji {
+        guild_id: u64,
+        emoji_id: u64,
+    },
+    EmojiRoles {
+        guild_id: u64,
+        emoji_id: u64,
+    },
+    Guild(u64),
+    GuildChannels(u64),
+    GuildEmojis(u64),
+    GuildFeatures(u64),
+    GuildMembers(u64),
+    GuildMemberSearch(u64),
+    GuildPlayer(u64),
+    GuildPresences(u64),
+    GuildRoles(u64),
+    GuildSettings(u64),
+    GuildSkipVotes(u64),
+    GuildVoiceStates(u64),
+    /// The Set of the IDs of all cached guilds.
+    Guilds,
+    History(u64),
+    Join(u64),
+    /// The hash of Lavalink nodes, keyed by address.
+    LavalinkNodes,
+    Member {
+        guild_id: u64,
+        user_id: u64,
+    },
+    MemberRoles {
+        guild_id: u64,
+        user_id: u64,
+    },
+    MemberSearchEntries {
+        guild_id: u64,
+        user_id: u64,
+    },
+    Presence {
+        guild_id: u64,
+        user_id: u64,
+    },
+    Queue(u64),
+    RatelimitFixed {
+        guild_id: u64,
+        user_id: u64,
+        command: String,
+    },
+    RatelimitSliding {
+        guild_id: u64,
+        user_id: u64,
+        command: String,
+    },
+    Role {
+        guild_id: u64,
+        role_id: u64,
+    },
+    ShardGuilds(u64),
+    SharderTo(u64),
+    User(u64),
+    /// The Set of the IDs of guilds in which a user has a voice state.
+    UserVoiceGuilds(u64),
+    /// The Pub/Sub channel on which voice connection info is published once
+    /// it's complete.
+    VoiceConnections,
+    VoiceState {
+        guild_id: u64,
+        user_id: u64,
+    },
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        use self::Key::*;
+
+        match self {
+            Channel(id) => write!(f, "ch:{}", id),
+            ChannelListeners(id) => write!(f, "ch:{}:l", id),
+            ChannelMessages(id) => write!(f, "ch:{}:m", id),
+            ChannelVoiceStates(id) => write!(f, "ch:{}:v", id),
+            Choice(id) => write!(f, "c:{}", id),
+            Emoji { guild_id, emoji_id } => write!(f, "g:{}:e:{}", guild_id, emoji_id),
+            EmojiRoles { guild_id, emoji_id } => write!(f, "g:{}:e:{}:r", guild_id, emoji_id),
+            Guild(id) => write!(f, "g:{}", id),
+            GuildChannels(id) => write!(f, "g:{}:c", id),
+            GuildEmojis(id) => write!(f, "g:{}:e", id),
+            GuildFeatures(id) => write!(f, "g:{}:f", id),
+            GuildMembers(id) => write!(f, "g:{}:m", id),
+            GuildMemberSearch(id) => write!(f, "g:{}:ms", id),
+            GuildPlayer(id) => write!(f, "g:{}:lhs", id),
+            GuildPresences(id) => write!(f, "g:{}:p", id),
+            GuildRoles(id) => write!(f, "g:{}:r", id),
+            GuildSettings(id) => write!(f, "g:{}:s", id),
+            GuildSkipVotes(id) => write!(f, "g:{}:sv", id),
+            GuildVoiceStates(id) => write!(f, "g:{}:v", id),
+            Guilds => f.write_str("guilds"),
+            History(id) => write!(f, "history:{}", id),
+            Join(id) => write!(f, "j:{}", id),
+            LavalinkNodes => f.write_str("ll:n"),
+            Member { guild_id, user_id } => write!(f, "g:{}:m:{}", guild_id, user_id),
+            MemberRoles { guild_id, user_id } => write!(f, "g:{}:m:{}:r", guild_id, user_id),
+            MemberSearchEntries { guild_id, user_id } => write!(f, "g:{}:m:{}:s", guild_id, user_id),
+            Presence { guild_id, user_id } => write!(f, "g:{}:p:{}", guild_id, user_id),
+            Queue(id) => write!(f, "queue:{}", id),
+            RatelimitFixed { guild_id, user_id, command } => {
+                write!(f, "rl:f:{}:{}:{}", guild_id, user_id, command)
+            },
+            RatelimitSliding { guild_id, user_id, command } => {
+                write!(f, "rl:s:{}:{}:{}", guild_id, user_id, command)
+            },
+            Role { guild_id, role_id } => write!(f, "g:{}:r:{}", guild_id, role_id),
+            ShardGuilds(id) => write!(f, "s:{}:g", id),
+            SharderTo(id) => write!(f, "sharder:to:{}", id),
+            User(id) => write!(f, "u:{}", id),
+            UserVoiceGuilds(id) => write!(f, "u:{}:v", id),
+            VoiceConnections => f.write_str("vc"),
+            VoiceState { guild_id, user_id } => write!(f, "g:{}:v:{}", guild_id, user_id),
+        }
+    }
+}
+
+impl FromStr for Key {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        use self::Key::*;
+
+        // Rate limit keys end with a command name, which may itself contain
+        // colons.
+        if s.starts_with("rl:") {
+            let parts = s.splitn(5, ':').collect::<Vec<_>>();
+
+            return match parts[..] {
+                ["rl", "f", guild_id, user_id, command] => Ok(RatelimitFixed {
+                    guild_id: id(guild_id)?,
+                    user_id: id(user_id)?,
+                    command: command.to_owned(),
+                }),
+                ["rl", "s", guild_id, user_id, command] => Ok(RatelimitSliding {
+                    guild_id: id(guild_id)?,
+                    user_id: id(user_id)?,
+                    command: command.to_owned(),
+                }),
+                _ => Err(Error::InvalidKey),
+            };
+        }
+
+        let parts = s.split(':').collect::<Vec<_>>();
+
+        Ok(match parts[..] {
+            ["ch", c] => Channel(id(c)?),
+            ["ch", c, "l"] => ChannelListeners(id(c)?),
+            ["ch", c, "m"] => ChannelMessages(id(c)?),
+            ["ch", c, "v"] => ChannelVoiceStates(id(c)?),
+            ["c", g] => Choice(id(g)?),
+            ["g", g] => Guild(id(g)?),
+            ["g", g, "c"] => GuildChannels(id(g)?),
+            ["g", g, "e"] => GuildEmojis(id(g)?),
+            ["g", g, "f"] => GuildFeatures(id(g)?),
+            ["g", g, "m"] => GuildMembers(id(g)?),
+            ["g", g, "ms"] => GuildMemberSearch(id(g)?),
+            ["g", g, "lhs"] => GuildPlayer(id(g)?),
+            ["g", g, "p"] => GuildPresences(id(g)?),
+            ["g", g, "r"] => GuildRoles(id(g)?),
+            ["g", g, "s"] => GuildSettings(id(g)?),
+            ["g", g, "sv"] => GuildSkipVotes(id(g)?),
+            ["g", g, "v"] => GuildVoiceStates(id(g)?),
+            ["g", g, "e", e] => Emoji {
+                guild_id: id(g)?,
+                emoji_id: id(e)?,
+            },
+            ["g", g, "e", e, "r"] => EmojiRoles {
+                guild_id: id(g)?,
+                emoji_id: id(e)?,
+            },
+            ["g", g, "m", u] => Member {
+                guild_id: id(g)?,
+                user_id: id(u)?,
+            },
+            ["g", g, "m", u, "r"] => MemberRoles {
+                guild_id: id(g)?,
+                user_id: id(u)?,
+            },
+            ["g", g, "m", u, "s"] => MemberSearchEntries {
+                guild_id: id(g)?,
+                user_id: id(u)?,
+            },
+            ["g", g, "p", u] => Presence {
+                guild_id: id(g)?,
+                user_id: id(u)?,
+            },
+            ["g", g, "r", r] => Role {
+                guild_id: id(g)?,
+                role_id: id(r)?,
+            },
+            ["g", g, "v", u] => VoiceState {
+                guild_id: id(g)?,
+                user_id: id(u)?,
+            },
+            ["guilds"] => Guilds,
+            ["history", g] => History(id(g)?),
+            ["j", g] => Join(id(g)?),
+            ["ll", "n"] => LavalinkNodes,
+            ["queue", g] => Queue(id(g)?),
+            ["s", shard, "g"] => ShardGuilds(id(shard)?),
+            ["sharder", "to", shard] => SharderTo(id(shard)?),
+            ["u", u] => User(id(u)?),
+            ["u", u, "v"] => UserVoiceGuilds(id(u)?),
+            ["vc"] => VoiceConnections,
+            _ => return Err(Error::InvalidKey),
+        })
+    }
+}
+
+fn id(s: &str) -> Result<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(Error::InvalidKey);
+    }
+
+    s.parse().map_err(|_| Error::InvalidKey)
+}
+
 pub fn channel(id: u64) -> String {
-    format!("ch:{}", id)
+    Key::Channel(id).to_string()
+}
+
+pub fn channel_listeners(id: u64) -> String {
+    Key::ChannelListeners(id).to_string()
+}
+
+pub fn channel_messages(id: u64) -> String {
+    Key::ChannelMessages(id).to_string()
 }
 
 pub fn channel_voice_states(id: u64) -> String {
-    format!("ch:{}:v", id)
+    Key::ChannelVoiceStates(id).to_string()
 }
 
 pub fn choice(id: u64) -> String {
-    format!("c:{}", id)
+    Key::Choice(id).to_string()
 }
 
 pub fn join(id: u64) -> String {
-    format!("j:{}", id)
+    Key::Join(id).to_string()
 }
 
 pub fn guild(id: u64) -> String {
-    format!("g:{}", id)
+    Key::Guild(id).to_string()
 }
 
 pub fn guild_channels(id: u64) -> String {
-    format!("g:{}:c", id)
+    Key::GuildChannels(id).to_string()
+}
+
+pub fn emoji(guild_id: u64, emoji_id: u64) -> String {
+    Key::Emoji { guild_id, emoji_id }.to_string()
+}
+
+pub fn emoji_roles(guild_id: u64, emoji_id: u64) -> String {
+    Key::EmojiRoles { guild_id, emoji_id }.to_string()
+}
+
+pub fn guild_emojis(id: u64) -> String {
+    Key::GuildEmojis(id).to_string()
 }
 
 pub fn guild_features(id: u64) -> String {
-    format!("g:{}:f", id)
+    Key::GuildFeatures(id).to_string()
 }
 
 pub fn guild_members(id: u64) -> String {
-    format!("g:{}:m", id)
+    Key::GuildMembers(id).to_string()
+}
+
+pub fn guild_member_search(id: u64) -> String {
+    Key::GuildMemberSearch(id).to_string()
 }
 
 pub fn guild_player(id: u64) -> String {
-    format!("g:{}:lhs", id)
+    Key::GuildPlayer(id).to_string()
+}
+
+pub fn guild_presences(id: u64) -> String {
+    Key::GuildPresences(id).to_string()
 }
 
 pub fn guild_roles(id: u64) -> String {
-    format!("g:{}:r", id)
+    Key::GuildRoles(id).to_string()
+}
+
+pub fn guild_settings(id: u64) -> String {
+    Key::GuildSettings(id).to_string()
+}
+
+pub fn guild_skip_votes(id: u64) -> String {
+    Key::GuildSkipVotes(id).to_string()
 }
 
 pub fn guild_voice_states(guild_id: u64) -> String {
-    format!("g:{}:v", guild_id)
+    Key::GuildVoiceStates(guild_id).to_string()
+}
+
+pub fn history(guild_id: u64) -> String {
+    Key::History(guild_id).to_string()
 }
 
 pub fn queue(guild_id: u64) -> String {
-    format!("queue:{}", guild_id)
+    Key::Queue(guild_id).to_string()
 }
 
 pub fn member(guild_id: u64, user_id: u64) -> String {
-    format!("g:{}:m:{}", guild_id, user_id)
+    Key::Member { guild_id, user_id }.to_string()
+}
+
+pub fn member_search_entries(guild_id: u64, user_id: u64) -> String {
+    Key::MemberSearchEntries { guild_id, user_id }.to_string()
 }
 
 pub fn member_roles(guild_id: u64, user_id: u64) -> String {
-    format!("g:{}:m:{}:r", guild_id, user_id)
+    Key::MemberRoles { guild_id, user_id }.to_string()
+}
+
+pub fn presence(guild_id: u64, user_id: u64) -> String {
+    Key::Presence { guild_id, user_id }.to_string()
+}
+
+pub fn ratelimit_fixed(guild_id: u64, user_id: u64, command: &str) -> String {
+    Key::RatelimitFixed {
+        guild_id,
+        user_id,
+        command: command.to_owned(),
+    }.to_string()
+}
+
+pub fn ratelimit_sliding(guild_id: u64, user_id: u64, command: &str) -> String {
+    Key::RatelimitSliding {
+        guild_id,
+        user_id,
+        command: command.to_owned(),
+    }.to_string()
 }
 
 pub fn role(guild_id: u64, role_id: u64) -> String {
-    format!("g:{}:r:{}", guild_id, role_id)
+    Key::Role { guild_id, role_id }.to_string()
+}
+
+pub fn user(id: u64) -> String {
+    Key::User(id).to_string()
+}
+
+pub fn user_voice_guilds(id: u64) -> String {
+    Key::UserVoiceGuilds(id).to_string()
 }
 
 pub fn user_voice_state(guild_id: u64, user_id: u64) -> String {
-    format!("g:{}:v:{}", guild_id, user_id)
+    Key::VoiceState { guild_id, user_id }.to_string()
+}
+
+pub fn guilds() -> String {
+    Key::Guilds.to_string()
+}
+
+pub fn lavalink_nodes() -> String {
+    Key::LavalinkNodes.to_string()
+}
+
+pub fn voice_connections() -> String {
+    Key::VoiceConnections.to_string()
+}
+
+pub fn shard_guilds(shard_id: u64) -> String {
+    Key::ShardGuilds(shard_id).to_string()
 }
 
 pub fn sharder_to(shard_id: u64) -> String {
-    format!("sharder:to:{}", shard_id)
+    Key::SharderTo(shard_id).to_string()
 }
 
 #[cfg(test)]
 mod tests {
+    use super::Key;
+
     #[test]
     fn test_channel() {
         assert_eq!(super::channel(381880193700069377), "ch:381880193700069377");
     }
 
+    #[test]
+    fn test_channel_listeners() {
+        assert_eq!(super::channel_listeners(2), "ch:2:l");
+    }
+
+    #[test]
+    fn test_channel_messages() {
+        assert_eq!(super::channel_messages(2), "ch:2:m");
+    }
+
     #[test]
     fn test_channel_voice_states() {
         assert_eq!(super::channel_voice_states(2), "ch:2:v");
@@ -93,6 +432,21 @@ mod tests {
         assert_eq!(super::guild_channels(2), "g:2:c");
     }
 
+    #[test]
+    fn test_emoji() {
+        assert_eq!(super::emoji(1, 2), "g:1:e:2");
+    }
+
+    #[test]
+    fn test_emoji_roles() {
+        assert_eq!(super::emoji_roles(1, 2), "g:1:e:2:r");
+    }
+
+    #[test]
+    fn test_guild_emojis() {
+        assert_eq!(super::guild_emojis(2), "g:2:e");
+    }
+
     #[test]
     fn test_guild_features() {
         assert_eq!(super::guild_features(2), "g:2:f");
@@ -103,21 +457,46 @@ mod tests {
         assert_eq!(super::guild_members(3), "g:3:m");
     }
 
+    #[test]
+    fn test_guild_member_search() {
+        assert_eq!(super::guild_member_search(3), "g:3:ms");
+    }
+
     #[test]
     fn test_guild_player() {
         assert_eq!(super::guild_player(4), "g:4:lhs");
     }
 
+    #[test]
+    fn test_guild_presences() {
+        assert_eq!(super::guild_presences(3), "g:3:p");
+    }
+
     #[test]
     fn test_guild_roles() {
         assert_eq!(super::guild_roles(3), "g:3:r");
     }
 
+    #[test]
+    fn test_guild_settings() {
+        assert_eq!(super::guild_settings(3), "g:3:s");
+    }
+
+    #[test]
+    fn test_guild_skip_votes() {
+        assert_eq!(super::guild_skip_votes(3), "g:3:sv");
+    }
+
     #[test]
     fn test_guild_voice_states() {
         assert_eq!(super::guild_voice_states(1), "g:1:v");
     }
 
+    #[test]
+    fn test_history() {
+        assert_eq!(super::history(272410239947767808), "history:272410239947767808");
+    }
+
     #[test]
     fn test_queue() {
         assert_eq!(super::queue(272410239947767808), "queue:272410239947767808");
@@ -128,11 +507,41 @@ mod tests {
         assert_eq!(super::member(1, 2), "g:1:m:2");
     }
 
+    #[test]
+    fn test_member_search_entries() {
+        assert_eq!(super::member_search_entries(1, 2), "g:1:m:2:s");
+    }
+
     #[test]
     fn test_member_roles() {
         assert_eq!(super::member_roles(1, 2), "g:1:m:2:r");
     }
 
+    #[test]
+    fn test_presence() {
+        assert_eq!(super::presence(1, 2), "g:1:p:2");
+    }
+
+    #[test]
+    fn test_ratelimit_fixed() {
+        assert_eq!(super::ratelimit_fixed(1, 2, "play"), "rl:f:1:2:play");
+    }
+
+    #[test]
+    fn test_ratelimit_sliding() {
+        assert_eq!(super::ratelimit_sliding(1, 2, "skip"), "rl:s:1:2:skip");
+    }
+
+    #[test]
+    fn test_user() {
+        assert_eq!(super::user(114941315417899012), "u:114941315417899012");
+    }
+
+    #[test]
+    fn test_user_voice_guilds() {
+        assert_eq!(super::user_voice_guilds(114941315417899012), "u:114941315417899012:v");
+    }
+
     #[test]
     fn test_user_voice_state() {
         assert_eq!(super::user_voice_state(1, 2), "g:1:v:2");
@@ -147,8 +556,70 @@ mod tests {
         assert_eq!(super::join(272410239947767808), "j:272410239947767808");
     }
 
+    #[test]
+    fn test_shard_guilds() {
+        assert_eq!(super::shard_guilds(3), "s:3:g");
+    }
+
     #[test]
     fn test_sharder_to() {
         assert_eq!(super::sharder_to(1337), "sharder:to:1337");
     }
+
+    #[test]
+    fn test_guilds() {
+        assert_eq!(super::guilds(), "guilds");
+    }
+
+    #[test]
+    fn test_lavalink_nodes() {
+        assert_eq!(super::lavalink_nodes(), "ll:n");
+    }
+
+    #[test]
+    fn test_voice_connections() {
+        assert_eq!(super::voice_connections(), "vc");
+    }
+
+    #[test]
+    fn test_key_round_trip() {
+        let keys = vec![
+            "ch:1", "ch:1:l", "ch:1:m", "ch:1:v", "c:1", "g:1", "g:1:c", "g:1:e",
+            "g:1:f", "g:1:m", "g:1:ms", "g:1:lhs", "g:1:p", "g:1:r", "g:1:s",
+            "g:1:sv", "g:1:v", "g:1:e:2", "g:1:e:2:r", "g:1:m:2", "g:1:m:2:r",
+            "g:1:m:2:s", "g:1:p:2", "g:1:r:2", "g:1:v:2", "guilds", "history:1", "j:1",
+            "ll:n", "queue:1", "rl:f:1:2:play", "rl:s:1:2:play:now", "s:1:g",
+            "sharder:to:1", "u:1", "u:1:v", "vc",
+        ];
+
+        for key in keys {
+            assert_eq!(key.parse::<Key>().unwrap().to_string(), key);
+        }
+    }
+
+    #[test]
+    fn test_key_parse() {
+        assert_eq!("g:1:m:2:r".parse::<Key>().unwrap(), Key::MemberRoles {
+            guild_id: 1,
+            user_id: 2,
+        });
+        assert_eq!("rl:s:1:2:play:now".parse::<Key>().unwrap(), Key::RatelimitSliding {
+            guild_id: 1,
+            user_id: 2,
+            command: "play:now".to_owned(),
+        });
+    }
+
+    #[test]
+    fn test_key_parse_invalid() {
+        assert!("".parse::<Key>().is_err());
+        assert!("g".parse::<Key>().is_err());
+        assert!("g:".parse::<Key>().is_err());
+        assert!("g:abc".parse::<Key>().is_err());
+        assert!("g:+1".parse::<Key>().is_err());
+        assert!("g:1:x".parse::<Key>().is_err());
+        assert!("g:1:m:2:x".parse::<Key>().is_err());
+        assert!("rl:x:1:2:play".parse::<Key>().is_err());
+        assert!("rl:f:1:2".parse::<Key>().is_err());
+    }
 }
@@ -0,0 +1,240 @@
+//! Encoding and decoding of models stored as Redis hashes.
+//!
+//! Models implement [`RespHash`] through `#[derive(RespHash)]` from the
+//! `dabbot-cache-derive` crate, so that the readers and writers of a hash
+//! agree on its field names and encodings.
+//!
+//! [`RespHash`]: trait.RespHash.html
+
+use crate::{
+    de,
+    error::Result,
+};
+use redis_async::{
+    error::Error as RedisError,
+    resp::RespValue,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serenity::model::permissions::Permissions;
+use std::collections::HashMap;
+
+/// A model that is stored as a Redis hash.
+pub trait RespHash: Sized {
+    /// Decodes the model from an HGETALL reply.
+    fn from_resp_hash(resp: RespValue) -> std::result::Result<Self, RedisError>;
+
+    /// Encodes the model into the hash fields to set and the hash fields to
+    /// delete.
+    fn to_resp_hash(&self) -> Result<HashChanges>;
+}
+
+/// A model whose encoding can't fail, because none of its fields are stored
+/// as JSON.
+///
+/// `#[derive(RespHash)]` implements this for such models, and their
+/// [`RespHash::to_resp_hash`] returns the same changes.
+///
+/// [`RespHash::to_resp_hash`]: trait.RespHash.html#tymethod.to_resp_hash
+pub trait ToHashChanges {
+    /// Encodes the model into the hash fields to set and the hash fields to
+    /// delete.
+    fn to_hash_changes(&self) -> HashChanges;
+}
+
+/// The changes to make to a hash: fields to set and fields to delete.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HashChanges {
+    /// Alternating field names and values, as taken by HMSET.
+    pub set: Vec<RespValue>,
+    /// The names of the fields to delete, as taken by HDEL.
+    pub delete: Vec<&'static str>,
+}
+
+impl HashChanges {
+    /// Sets a field to a value.
+    pub fn set(&mut self, field: &'static str, value: &impl ToRespField) {
+        self.set.push(RespValue::from(field));
+        self.set.push(value.to_resp_field());
+    }
+
+    /// Sets a field to the JSON encoding of a value.
+    pub fn set_json(&mut self, field: &'static str, value: &impl Serialize) -> Result<()> {
+        let bytes = serde_json::to_vec(value)?;
+
+        self.set.push(RespValue::from(field));
+        self.set.push(RespValue::BulkString(bytes));
+
+        Ok(())
+    }
+
+    /// Deletes a field.
+    pub fn delete(&mut self, field: &'static str) {
+        self.delete.push(field);
+    }
+
+    /// Whether there are no changes.
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty() && self.delete.is_empty()
+    }
+}
+
+/// A value that can be stored in a hash field.
+pub trait ToRespField {
+    fn to_resp_field(&self) -> RespValue;
+}
+
+impl ToRespField for bool {
+    fn to_resp_field(&self) -> RespValue {
+        RespValue::from(usize::from(*self))
+    }
+}
+
+impl ToRespField for Permissions {
+    fn to_resp_field(&self) -> RespValue {
+        RespValue::from(self.bits() as usize)
+    }
+}
+
+impl ToRespField for String {
+    fn to_resp_field(&self) -> RespValue {
+        RespValue::from(self.clone())
+    }
+}
+
+macro to_resp_field_unsigned($($ty:ty,)+) {
+    $(
+        impl ToRespField for $ty {
+            fn to_resp_field(&self) -> RespValue {
+                RespValue::from(*self as usize)
+            }
+        }
+    )+
+}
+
+to_resp_field_unsigned![
+    u8,
+    u16,
+    u32,
+    u64,
+    usize,
+];
+
+/// The fields of an HGETALL reply, taken out one at a time by a decoder.
+pub struct HashFields(HashMap<String, RespValue>);
+
+impl HashFields {
+    pub fn new(resp: RespValue) -> std::result::Result<Self, RedisError> {
+        let values = match resp {
+            RespValue::Array(values) => values,
+            _ => return Err(RedisError::RESP("Expected an array".to_owned(), None)),
+        };
+
+        let mut fields = HashMap::with_capacity(values.len() / 2);
+        let mut iter = values.into_iter();
+
+        while let Some(field) = iter.next() {
+            let field = match field {
+                RespValue::BulkString(bytes) => String::from_utf8(bytes).map_err(|_| {
+                    RedisError::RESP("Hash field isn't UTF-8".to_owned(), None)
+                })?,
+                RespValue::SimpleString(field) => field,
+                _ => return Err(RedisError::RESP("Expected a hash field".to_owned(), None)),
+            };
+            let value = iter.next().ok_or_else(|| {
+                RedisError::RESP(format!("Hash field {} has no value", field), None)
+            })?;
+
+            fields.insert(field, value);
+        }
+
+        Ok(HashFields(fields))
+    }
+
+    /// Takes and decodes a field, if it exists.
+    pub fn take<T: DeserializeOwned>(
+        &mut self,
+        field: &str,
+    ) -> std::result::Result<Option<T>, RedisError> {
+        match self.0.remove(field) {
+            Some(value) => de::from_resp(value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Takes and decodes a field holding JSON, if it exists.
+    pub fn take_json<T: DeserializeOwned>(
+        &mut self,
+        field: &str,
+    ) -> std::result::Result<Option<T>, RedisError> {
+        let bytes = match self.0.remove(field) {
+            Some(RespValue::BulkString(bytes)) => bytes,
+            Some(RespValue::Nil) | None => return Ok(None),
+            Some(other) => return Err(RedisError::Unexpected(format!(
+                "Expected JSON in field {}: {:?}",
+                field,
+                other,
+            ))),
+        };
+
+        serde_json::from_slice(&bytes).map(Some).map_err(|why| {
+            RedisError::Unexpected(format!(
+                "Couldn't deserialize field {} as JSON: err={}",
+                field,
+                why,
+            ))
+        })
+    }
+
+    /// The error for a required field that's missing.
+    pub fn missing(field: &str) -> RedisError {
+        RedisError::Unexpected(format!("Missing hash field {}", field))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use redis_async::resp::RespValue;
+    use super::{HashChanges, HashFields};
+
+    #[test]
+    fn test_changes() {
+        let mut changes = HashChanges::default();
+        assert!(changes.is_empty());
+
+        changes.set("deaf", &true);
+        changes.set("name", &"0123".to_owned());
+        changes.set_json("roles", &vec![1, 2]).unwrap();
+        changes.delete("nick");
+
+        assert_eq!(changes.set, vec![
+            RespValue::from("deaf"),
+            RespValue::from(1usize),
+            RespValue::from("name"),
+            RespValue::from("0123".to_owned()),
+            RespValue::from("roles"),
+            RespValue::BulkString(b"[1,2]".to_vec()),
+        ]);
+        assert_eq!(changes.delete, vec!["nick"]);
+    }
+
+    #[test]
+    fn test_fields() {
+        let mut fields = HashFields::new(RespValue::Array(vec![
+            RespValue::BulkString(b"id".to_vec()),
+            RespValue::BulkString(b"5".to_vec()),
+            RespValue::BulkString(b"roles".to_vec()),
+            RespValue::BulkString(b"[1,2]".to_vec()),
+        ])).unwrap();
+
+        assert_eq!(fields.take::<u64>("id").unwrap(), Some(5));
+        assert_eq!(fields.take::<u64>("id").unwrap(), None);
+        assert_eq!(fields.take_json::<Vec<u64>>("roles").unwrap(), Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn test_fields_odd() {
+        let value = RespValue::Array(vec![RespValue::BulkString(b"id".to_vec())]);
+
+        assert!(HashFields::new(value).is_err());
+    }
+}
@@ -0,0 +1,273 @@
+use crate::{
+    cache::Cache,
+    error::{Error, Result},
+    gen,
+    model::{LavalinkNode, PlayerPatch},
+    ratelimit::millis,
+    resp_impl::RespValueExt,
+};
+use redis_async::resp::RespValue;
+
+/// Records a node's heartbeat, stamping it with the current time in Redis.
+///
+/// `KEYS[1]` is the node hash and `ARGV[1]` is the JSON of the node, whose
+/// `last_heartbeat` is replaced.
+const HEARTBEAT_SCRIPT: &str = r#"
+redis.replicate_commands()
+local time = redis.call('TIME')
+local node = cjson.decode(ARGV[1])
+node.last_heartbeat = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+redis.call('HSET', KEYS[1], node.address, cjson.encode(node))
+"#;
+
+/// Keeps a guild on its assigned node while the node is alive, and otherwise
+/// assigns it to the least loaded live node.
+///
+/// `KEYS[1]` is the node hash and `KEYS[2]` is the guild's player hash.
+/// `ARGV[1]` is the heartbeat timeout in milliseconds and `ARGV[2]` is the
+/// preferred region. Heartbeats are compared against the time in Redis, which
+/// is also what they were recorded with.
+///
+/// Nodes in the preferred region are picked over others. Between those, the
+/// node with the fewest players is picked, then the one with the lowest CPU
+/// load. The picked node's player count is incremented so that guilds
+/// assigned before its next heartbeat are spread out.
+///
+/// Returns the assigned node, whether the assignment changed, and the
+/// previously assigned node.
+const ASSIGN_SCRIPT: &str = r#"
+redis.replicate_commands()
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local timeout = tonumber(ARGV[1])
+local region = ARGV[2]
+local function alive(node)
+    return now - node.last_heartbeat <= timeout
+end
+local current = redis.call('HGET', KEYS[2], 'node')
+if current then
+    local raw = redis.call('HGET', KEYS[1], current)
+    if raw and alive(cjson.decode(raw)) then
+        return {current, 0, current}
+    end
+end
+local best, best_node, best_in_region
+local entries = redis.call('HGETALL', KEYS[1])
+for i = 1, #entries, 2 do
+    local node = cjson.decode(entries[i + 1])
+    if alive(node) then
+        local in_region = node.region == region
+        local better = best == nil
+            or (in_region and not best_in_region)
+            or (in_region == best_in_region and (node.players < best_node.players
+                or (node.players == best_node.players and node.cpu_load < best_node.cpu_load)))
+        if better then
+            best, best_node, best_in_region = entries[i], node, in_region
+        end
+    end
+end
+if not best then
+    return {false, 0, current}
+end
+best_node.players = best_node.players + 1
+redis.call('HSET', KEYS[1], best, cjson.encode(best_node))
+redis.call('HSET', KEYS[2], 'node', best)
+return {best, 1, current}
+"#;
+
+/// A guild's assignment to a Lavalink node.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NodeAssignment {
+    /// The address of the assigned node.
+    pub node: String,
+    /// Whether the guild was just assigned to the node, in which case its
+    /// player needs to be created there.
+    pub changed: bool,
+    /// The address of the node the guild was assigned to before, if it
+    /// changed. Its player there, if the node comes back, should be
+    /// destroyed.
+    pub previous: Option<String>,
+}
+
+impl NodeAssignment {
+    fn from_reply(values: Vec<RespValue>) -> Option<Self> {
+        let mut values = values.into_iter();
+        let node = string_or_nil(values.next()?)?;
+        let changed = values.next()? == RespValue::Integer(1);
+        let previous = string_or_nil(values.next()?);
+
+        Some(Self {
+            node,
+            changed,
+            previous: if changed { previous } else { None },
+        })
+    }
+}
+
+fn string_or_nil(value: RespValue) -> Option<String> {
+    match value {
+        RespValue::Nil => None,
+        other => Some(other.into_string()),
+    }
+}
+
+/// Lavalink nodes.
+///
+/// Nodes record heartbeats with their stats, and guilds are assigned to nodes
+/// in the cache so that every worker sends a guild's player to the same node.
+/// A guild keeps its node until the node misses heartbeats for longer than
+/// the configured [`lavalink_heartbeat_timeout`].
+///
+/// [`lavalink_heartbeat_timeout`]: struct.Config.html#structfield.lavalink_heartbeat_timeout
+impl Cache {
+    /// Records a heartbeat from a Lavalink node with its current stats,
+    /// adding the node to the registry if it's new.
+    ///
+    /// The heartbeat's time is taken from Redis, so workers with skewed clocks
+    /// agree on which nodes are alive.
+    pub async fn record_lavalink_heartbeat<'a>(
+        &'a self,
+        address: &'a str,
+        region: &'a str,
+        players: u64,
+        cpu_load: f64,
+    ) -> Result<()> {
+        let node = LavalinkNode {
+            address: address.to_owned(),
+            cpu_load,
+            // Set by the script.
+            last_heartbeat: 0,
+            players,
+            region: region.to_owned(),
+        };
+        let json = serde_json::to_string(&node)?;
+
+        await!(self.inner().eval::<RespValue>(
+            HEARTBEAT_SCRIPT,
+            vec![gen::lavalink_nodes()],
+            vec![RespValue::from(json)],
+        ))?;
+
+        Ok(())
+    }
+
+    /// Removes a Lavalink node from the registry.
+    ///
+    /// Guilds assigned to it are reassigned when next assigned.
+    pub async fn remove_lavalink_node<'a>(&'a self, address: &'a str) -> Result<()> {
+        await!(self.inner().hdel(gen::lavalink_nodes(), vec![address]))
+    }
+
+    /// Gets all registered Lavalink nodes, including those whose heartbeats
+    /// have gone stale.
+    pub async fn get_lavalink_nodes(&self) -> Result<Vec<LavalinkNode>> {
+        let values = await!(self.inner().hgetall(gen::lavalink_nodes()))?.into_array();
+
+        values
+            .into_iter()
+            .skip(1)
+            .step_by(2)
+            .map(|value| match value {
+                RespValue::BulkString(bytes) => {
+                    serde_json::from_slice(&bytes).map_err(From::from)
+                },
+                _ => Err(Error::None),
+            })
+            .collect()
+    }
+
+    /// Gets the address of the Lavalink node a guild is assigned to, if any.
+    ///
+    /// The node may be down; use [`assign_lavalink_node`] to get a live one.
+    ///
+    /// [`assign_lavalink_node`]: #method.assign_lavalink_node
+    pub async fn get_guild_lavalink_node(
+        &self,
+        guild_id: u64,
+    ) -> Result<Option<String>> {
+        await!(self.inner().hget(gen::guild_player(guild_id), "node".to_owned()))
+    }
+
+    /// Gets the Lavalink node for a guild, assigning it to the least loaded
+    /// live node if it isn't assigned or its node has gone stale.
+    ///
+    /// Nodes in `region` are preferred. Assignment happens atomically in
+    /// Redis, so concurrent workers always agree on the node.
+    ///
+    /// Returns `None` if no nodes are alive.
+    pub async fn assign_lavalink_node<'a>(
+        &'a self,
+        guild_id: u64,
+        region: Option<&'a str>,
+    ) -> Result<Option<NodeAssignment>> {
+        let values: Vec<RespValue> = await!(self.inner().eval(
+            ASSIGN_SCRIPT,
+            vec![gen::lavalink_nodes(), gen::guild_player(guild_id)],
+            vec![
+                RespValue::from(millis(self.config().lavalink_heartbeat_timeout) as usize),
+                RespValue::from(region.unwrap_or_default()),
+            ],
+        ))?;
+
+        Ok(NodeAssignment::from_reply(values))
+    }
+
+    /// Removes a guild's Lavalink node assignment, such as when its player is
+    /// destroyed.
+    pub async fn delete_guild_lavalink_node(&self, guild_id: u64) -> Result<()> {
+        let changes = PlayerPatch {
+            node: Some(None),
+            ..PlayerPatch::default()
+        }.into_changes();
+
+        await!(self.inner().hdel(gen::guild_player(guild_id), changes.delete))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use redis_async::resp::RespValue;
+    use super::NodeAssignment;
+
+    fn bulk(s: &str) -> RespValue {
+        RespValue::BulkString(s.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_assignment_unchanged() {
+        let reply = vec![bulk("a:2333"), RespValue::Integer(0), bulk("a:2333")];
+
+        assert_eq!(NodeAssignment::from_reply(reply), Some(NodeAssignment {
+            node: "a:2333".to_owned(),
+            changed: false,
+            previous: None,
+        }));
+    }
+
+    #[test]
+    fn test_assignment_reassigned() {
+        let reply = vec![bulk("b:2333"), RespValue::Integer(1), bulk("a:2333")];
+
+        assert_eq!(NodeAssignment::from_reply(reply), Some(NodeAssignment {
+            node: "b:2333".to_owned(),
+            changed: true,
+            previous: Some("a:2333".to_owned()),
+        }));
+    }
+
+    #[test]
+    fn test_assignment_new() {
+        let reply = vec![bulk("b:2333"), RespValue::Integer(1), RespValue::Nil];
+
+        let assignment = NodeAssignment::from_reply(reply).unwrap();
+        assert!(assignment.changed);
+        assert!(assignment.previous.is_none());
+    }
+
+    #[test]
+    fn test_assignment_no_live_nodes() {
+        let reply = vec![RespValue::Nil, RespValue::Integer(0), bulk("a:2333")];
+
+        assert!(NodeAssignment::from_reply(reply).is_none());
+    }
+}
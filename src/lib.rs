@@ -9,19 +9,41 @@
     underscore_imports,
 )]
 
+#[macro_use] extern crate bitflags;
 #[macro_use] extern crate log;
 #[macro_use] extern crate redis_async;
 #[macro_use] extern crate serde;
 
+#[cfg(feature = "blocking")]
+pub mod blocking;
 pub mod model;
+#[cfg(feature = "test-util")]
+pub mod test_util;
 
 mod cache;
 mod commands;
+mod config;
+mod de;
 mod error;
 mod gen;
+mod hash;
+mod lavalink;
+mod ratelimit;
 mod resp_impl;
+mod search;
+mod shard;
+mod skip_votes;
+mod voice;
+mod writes;
 
 pub use crate::{
     cache::Cache,
+    config::{Config, RetryPolicy},
     error::{Error, Result},
+    gen::Key,
+    hash::{HashChanges, RespHash, ToHashChanges},
+    lavalink::NodeAssignment,
+    ratelimit::RateLimit,
+    shard::shard_id,
+    writes::{WriteError, WriteErrorHandler},
 };
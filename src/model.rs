@@ -1,103 +1,521 @@
+use chrono::{DateTime, FixedOffset};
 use crate::{
     error::Error as CacheError,
-    resp_impl::RespValueExt,
+    gen,
+    hash::{HashChanges, RespHash, ToRespField},
 };
+use dabbot_cache_derive::RespHash;
 use redis_async::{
     error::Error as RedisError,
-    resp::{FromResp, RespValue},
+    resp::RespValue,
+};
+use serenity::model::{
+    permissions::Permissions,
+    user::User as DiscordUser,
 };
-use serde::de::DeserializeOwned;
-use serde_aux::prelude::*;
-use serde_json::{Map, Number, Value};
-use serenity::model::permissions::Permissions;
 use std::{
     collections::HashSet,
     convert::TryFrom,
 };
 
-fn convert<T: DeserializeOwned>(resp: RespValue) -> Result<T, RedisError> {
-    let values = match resp {
-        RespValue::Array(x) => x,
-        _ => return Err(RedisError::RESP("Expected an array".to_owned(), None)),
-    };
-
-    let map = create_hashmap(values);
-
-    // Should this really not panic? Is it better to let the user handle the error, or should we
-    // force unwinds for them to realise what happened?
-    //
-    // Ok(serde_json::from_value(Value::from(map)).expect("err deserializing"))
-
-    match serde_json::from_value(Value::from(map)) {
-        Ok(deserialized) => Ok(deserialized),
-        Err(err) => Err(RedisError::Unexpected(format!("Couldn't deserialize a cached value: err={:?}", err))),
-    }
+#[derive(Clone, Debug, Deserialize, RespHash, Serialize)]
+pub struct Emoji {
+    pub animated: bool,
+    pub id: u64,
+    pub name: String,
+    #[resp(default, skip_encoding)]
+    #[serde(default)]
+    pub roles: HashSet<u64>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A cached guild.
+///
+/// The ID Sets are only populated for the [`GuildParts`] that were requested
+/// when the guild was read, and are empty otherwise.
+///
+/// [`GuildParts`]: struct.GuildParts.html
+#[derive(Clone, Debug, Default, Deserialize, RespHash, Serialize)]
 pub struct Guild {
+    #[resp(delete_none)]
     pub afk_channel_id: Option<u64>,
+    #[resp(default, skip_encoding)]
+    #[serde(default)]
     pub channels: HashSet<u64>,
+    #[resp(default, skip_encoding)]
+    #[serde(default)]
+    pub emojis: HashSet<u64>,
+    #[resp(default, skip_encoding)]
+    #[serde(default)]
     pub features: HashSet<String>,
+    #[resp(default, skip_encoding)]
+    #[serde(default)]
     pub members: HashSet<u64>,
     pub name: String,
-    #[serde(deserialize_with = "deserialize_number_from_string")]
     pub owner_id: u64,
     pub region: String,
+    #[resp(default, skip_encoding)]
+    #[serde(default)]
     pub roles: HashSet<u64>,
+    #[resp(default, skip_encoding)]
+    #[serde(default)]
     pub voice_states: HashSet<u64>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+bitflags! {
+    /// The parts of a guild to read in addition to its hash, which contains
+    /// fields like the name and owner.
+    ///
+    /// Each part is a Set of IDs, some of which (such as members) may be very
+    /// large, so only request what is needed.
+    pub struct GuildParts: u8 {
+        const CHANNELS = 1;
+        const EMOJIS = 1 << 1;
+        const FEATURES = 1 << 2;
+        const MEMBERS = 1 << 3;
+        const ROLES = 1 << 4;
+        const VOICE_STATES = 1 << 5;
+    }
+}
+
+impl GuildParts {
+    /// Returns the guild field names and Set keys of the selected parts.
+    pub(crate) fn keys(self, guild_id: u64) -> Vec<(&'static str, String)> {
+        let parts: [(GuildParts, &'static str, fn(u64) -> String); 6] = [
+            (GuildParts::CHANNELS, "channels", gen::guild_channels),
+            (GuildParts::EMOJIS, "emojis", gen::guild_emojis),
+            (GuildParts::FEATURES, "features", gen::guild_features),
+            (GuildParts::MEMBERS, "members", gen::guild_members),
+            (GuildParts::ROLES, "roles", gen::guild_roles),
+            (GuildParts::VOICE_STATES, "voice_states", gen::guild_voice_states),
+        ];
+
+        parts
+            .iter()
+            .filter(|(part, _, _)| self.contains(*part))
+            .map(|(_, name, key)| (*name, key(guild_id)))
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, RespHash, Serialize)]
 pub struct GuildChannel {
     pub bitrate: Option<u64>,
     pub category_id: Option<u64>,
-    #[serde(deserialize_with = "deserialize_number_from_string")]
     pub kind: u64,
     pub name: String,
+    #[resp(json)]
     pub permission_overwrites: Vec<PermissionOverwrite>,
     pub user_limit: Option<u64>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A guild's bot configuration.
+///
+/// Fields that a guild hasn't configured take their values from the
+/// [`Default`] implementation when read.
+///
+/// [`Default`]: #impl-Default
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, RespHash, Serialize)]
+#[resp(default)]
+#[serde(default)]
+pub struct GuildSettings {
+    /// The channel to announce new tracks in.
+    pub announce_channel_id: Option<u64>,
+    /// The volume that players start at, as a percentage.
+    pub default_volume: u64,
+    /// The role that members must have to use DJ commands.
+    pub dj_role_id: Option<u64>,
+    /// The maximum number of tracks in the queue.
+    pub max_queue_length: u64,
+    /// The maximum duration of a queued track, in seconds.
+    pub max_track_duration: u64,
+    /// The command prefix.
+    pub prefix: String,
+}
+
+impl Default for GuildSettings {
+    fn default() -> Self {
+        Self {
+            announce_channel_id: None,
+            default_volume: 100,
+            dj_role_id: None,
+            max_queue_length: 1000,
+            max_track_duration: 60 * 60 * 3,
+            prefix: "!".to_owned(),
+        }
+    }
+}
+
+/// A partial update to a guild's settings.
+///
+/// Fields that are `None` are left unchanged. The optional settings are
+/// cleared when set to `Some(None)`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GuildSettingsPatch {
+    pub announce_channel_id: Option<Option<u64>>,
+    pub default_volume: Option<u64>,
+    pub dj_role_id: Option<Option<u64>>,
+    pub max_queue_length: Option<u64>,
+    pub max_track_duration: Option<u64>,
+    pub prefix: Option<String>,
+}
+
+impl GuildSettingsPatch {
+    /// Converts the patch into the hash fields to set and delete.
+    pub(crate) fn into_changes(self) -> HashChanges {
+        let mut changes = HashChanges::default();
+
+        match self.announce_channel_id {
+            Some(Some(id)) => changes.set("announce_channel_id", &id),
+            Some(None) => changes.delete("announce_channel_id"),
+            None => {},
+        }
+
+        if let Some(volume) = self.default_volume {
+            changes.set("default_volume", &volume);
+        }
+
+        match self.dj_role_id {
+            Some(Some(id)) => changes.set("dj_role_id", &id),
+            Some(None) => changes.delete("dj_role_id"),
+            None => {},
+        }
+
+        if let Some(length) = self.max_queue_length {
+            changes.set("max_queue_length", &length);
+        }
+
+        if let Some(duration) = self.max_track_duration {
+            changes.set("max_track_duration", &duration);
+        }
+
+        if let Some(prefix) = self.prefix {
+            changes.set("prefix", &prefix);
+        }
+
+        changes
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, RespHash, Serialize)]
 pub struct Member {
     pub deaf: bool,
+    #[resp(json)]
+    #[serde(default)]
+    pub joined_at: Option<DateTime<FixedOffset>>,
+    pub mute: bool,
+    #[resp(delete_none)]
+    #[serde(default)]
     pub nick: Option<String>,
+    /// The member's role IDs, stored in their own Set.
+    #[resp(skip_encoding)]
     pub roles: Vec<u64>,
+    /// The member's user, stored in the global user cache.
+    #[resp(skip_encoding)]
     pub user: User,
+    pub user_id: u64,
+}
+
+impl Member {
+    /// Creates a member from its cached hash, with the hash of its globally
+    /// cached user joined in under the `user` field.
+    pub(crate) fn from_resp_with_user(
+        member: RespValue,
+        user: RespValue,
+    ) -> Result<Self, RedisError> {
+        let mut member = match member {
+            RespValue::Array(member) => member,
+            _ => return Err(RedisError::RESP("Expected an array".to_owned(), None)),
+        };
+
+        member.push(RespValue::from("user"));
+        member.push(user);
+
+        Self::from_resp_hash(RespValue::Array(member))
+    }
+}
+
+/// A partial update to a member, such as from a GuildMemberUpdate event.
+///
+/// Fields that are `None` are left unchanged. The nickname is cleared when set
+/// to `Some(None)`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct MemberPatch {
+    pub nick: Option<Option<String>>,
+}
+
+impl MemberPatch {
+    /// Converts the patch into the hash fields to set and delete.
+    pub(crate) fn into_changes(self) -> HashChanges {
+        let mut changes = HashChanges::default();
+
+        match self.nick {
+            Some(Some(nick)) => changes.set("nick", &nick),
+            Some(None) => changes.delete("nick"),
+            None => {},
+        }
+
+        changes
+    }
+}
+
+/// A track that finished playing in a guild.
+///
+/// These are stored as JSON entries in a guild's capped history list.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct HistoryEntry {
+    /// The Unix timestamp in seconds of when the track finished playing.
+    pub played_at: i64,
+    /// The ID of the user who queued the track.
+    pub requester_id: u64,
+    /// The encoded track.
+    pub track: String,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Metadata about a recently sent message.
+///
+/// These are stored as JSON entries in a channel's capped message list rather
+/// than as hashes.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Message {
+    pub author_id: u64,
+    pub content_hash: u64,
+    pub id: u64,
+    /// The Unix timestamp in seconds of when the message was sent.
+    pub timestamp: i64,
+}
+
+impl Message {
+    /// Hashes message content using 64-bit FNV-1a.
+    ///
+    /// This is stable across processes and Rust versions, unlike the standard
+    /// library's hasher, so hashes can be compared between bot workers.
+    pub fn hash_content(content: &str) -> u64 {
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0100_0000_01b3;
+
+        content.bytes().fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, RespHash, Serialize)]
 pub struct PermissionOverwrite {
     pub allow: Permissions,
     pub deny: Permissions,
-    #[serde(deserialize_with = "deserialize_number_from_string")]
     pub kind: u64,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, RespHash, Serialize)]
+pub struct Presence {
+    #[resp(delete_none)]
+    #[serde(default)]
+    pub activity_kind: Option<u64>,
+    #[resp(delete_none)]
+    #[serde(default)]
+    pub activity_name: Option<String>,
+    pub status: String,
+}
+
+impl Presence {
+    /// Whether the activity is of the "Listening to" type.
+    pub fn is_listening(&self) -> bool {
+        self.activity_kind == Some(2)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, RespHash, Serialize)]
 pub struct Role {
-    #[serde(deserialize_with = "deserialize_string_from_number")]
+    pub colour: u64,
     pub name: String,
     pub permissions: Permissions,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A tally of the skip votes for a guild's current track.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SkipVotes {
+    /// The number of members listening in the bot's voice channel.
+    pub listeners: u64,
+    /// The number of listeners who have voted to skip.
+    pub votes: u64,
+}
+
+impl SkipVotes {
+    /// The number of votes needed for a skip, given the ratio of listeners
+    /// that must vote.
+    ///
+    /// At least one vote is always needed.
+    pub fn required(&self, ratio: f64) -> u64 {
+        let required = (self.listeners as f64 * ratio).ceil() as u64;
+
+        required.max(1)
+    }
+
+    /// Whether enough listeners have voted to skip, given the ratio of
+    /// listeners that must vote.
+    pub fn passes(&self, ratio: f64) -> bool {
+        self.votes >= self.required(ratio)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, RespHash, Serialize)]
 pub struct User {
     pub bot: bool,
-    #[serde(deserialize_with = "deserialize_number_from_string")]
     pub discriminator: u16,
-    #[serde(deserialize_with = "deserialize_number_from_string")]
     pub id: u64,
     pub name: String,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+impl<'a> From<&'a DiscordUser> for User {
+    fn from(user: &'a DiscordUser) -> Self {
+        Self {
+            bot: user.bot,
+            discriminator: user.discriminator,
+            id: user.id.0,
+            name: user.name.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, RespHash, Serialize)]
 pub struct VoiceState {
-    #[serde(deserialize_with = "deserialize_number_from_string")]
     pub channel_id: u64,
-    #[serde(deserialize_with = "deserialize_string_from_number")]
+    #[resp(default)]
+    #[serde(default)]
+    pub deaf: bool,
+    /// The voice server's endpoint, written separately from the rest of the
+    /// state when the bot's own voice server is updated.
+    #[resp(skip_encoding)]
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    #[resp(default)]
+    #[serde(default)]
+    pub mute: bool,
+    #[resp(default)]
+    #[serde(default)]
+    pub self_deaf: bool,
+    #[resp(default)]
+    #[serde(default)]
+    pub self_mute: bool,
     pub session_id: String,
+    #[resp(default)]
+    #[serde(default)]
+    pub suppress: bool,
+    /// The voice server's token. Voice state updates don't usually include
+    /// it, so it's left untouched when `None` rather than deleting the one
+    /// from the last voice server update, unless the session changed.
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// A partial update to a voice state's voice server details, which arrive
+/// separately from the rest of the state.
+///
+/// Fields that are `None` are left unchanged, and are cleared when set to
+/// `Some(None)`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct VoiceStatePatch {
+    pub endpoint: Option<Option<String>>,
+    pub token: Option<Option<String>>,
+}
+
+impl VoiceStatePatch {
+    /// Converts the patch into the hash fields to set and delete.
+    pub(crate) fn into_changes(self) -> HashChanges {
+        let mut changes = HashChanges::default();
+
+        match self.endpoint {
+            Some(Some(endpoint)) => changes.set("endpoint", &endpoint),
+            Some(None) => changes.delete("endpoint"),
+            None => {},
+        }
+
+        match self.token {
+            Some(Some(token)) => changes.set("token", &token),
+            Some(None) => changes.delete("token"),
+            None => {},
+        }
+
+        changes
+    }
+}
+
+impl VoiceState {
+    /// Whether the member is deafened, either by themselves or by the guild.
+    pub fn is_deafened(&self) -> bool {
+        self.deaf || self.self_deaf
+    }
+
+    /// Returns the details needed to connect to the voice server, if the
+    /// session ID, token, and endpoint are all known.
+    pub fn connection_info(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+    ) -> Option<VoiceConnectionInfo> {
+        Some(VoiceConnectionInfo {
+            channel_id: self.channel_id,
+            endpoint: self.endpoint.clone()?,
+            guild_id,
+            session_id: self.session_id.clone(),
+            token: self.token.clone()?,
+            user_id,
+        })
+    }
+}
+
+/// The details an audio node needs to connect to a guild's voice server on
+/// behalf of a user, normally the bot.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct VoiceConnectionInfo {
+    pub channel_id: u64,
+    pub endpoint: String,
+    pub guild_id: u64,
+    pub session_id: String,
+    pub token: String,
+    pub user_id: u64,
+}
+
+/// A report of the dangling references found in a guild's cached data.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GuildInconsistencies {
+    /// IDs of users in the guild's member Set with no member hash.
+    pub dangling_members: Vec<u64>,
+    /// Pairs of user IDs and role IDs where the role is in the member's role
+    /// Set but not in the guild's role Set.
+    pub dangling_member_roles: Vec<(u64, u64)>,
+    /// Pairs of channel IDs and user IDs where the user is in the channel's
+    /// voice state Set but their voice state is missing or points elsewhere.
+    pub stale_channel_voice_states: Vec<(u64, u64)>,
+}
+
+impl GuildInconsistencies {
+    /// Whether no inconsistencies were found.
+    pub fn is_empty(&self) -> bool {
+        self.dangling_members.is_empty()
+            && self.dangling_member_roles.is_empty()
+            && self.stale_channel_voice_states.is_empty()
+    }
+}
+
+/// A Lavalink node, as of its last heartbeat.
+///
+/// Whether a node is alive is only decided in Redis, when assigning guilds to
+/// nodes with [`Cache::assign_lavalink_node`].
+///
+/// [`Cache::assign_lavalink_node`]: ../struct.Cache.html#method.assign_lavalink_node
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LavalinkNode {
+    pub address: String,
+    /// The system CPU load reported by the node, from 0 to 1.
+    pub cpu_load: f64,
+    /// When the node last sent a heartbeat, in milliseconds since the Unix
+    /// epoch.
+    pub last_heartbeat: u64,
+    /// The number of players on the node, including guilds assigned to it
+    /// since its last heartbeat.
+    pub players: u64,
+    pub region: String,
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
@@ -139,71 +557,251 @@ impl TryFrom<String> for LoopMode {
     }
 }
 
-fn create_hashmap(resp: Vec<RespValue>) -> Map<String, Value> {
-    let mut map = Map::with_capacity(resp.len() / 2);
-    let mut iter = resp.into_iter();
+impl ToRespField for LoopMode {
+    fn to_resp_field(&self) -> RespValue {
+        let mode: String = (*self).into();
 
-    loop {
-        let key = match iter.next() {
-            Some(key) => key,
-            None => break,
-        };
-        let value = iter.next().unwrap();
-        let v = resp_to_value(value);
-        map.insert(key.into_string(), v);
+        RespValue::from(mode)
     }
+}
 
-    map
+/// A partial update to a guild's player hash.
+///
+/// Fields that are `None` are left unchanged. The node and track are cleared
+/// when set to `Some(None)`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct PlayerPatch {
+    pub loop_mode: Option<LoopMode>,
+    pub node: Option<Option<String>>,
+    pub track: Option<Option<String>>,
 }
 
-fn resp_to_value(resp: RespValue) -> Value {
-    match resp {
-        RespValue::Nil => Value::Null,
-        RespValue::Array(resps) => Value::Array(resps.into_iter().map(resp_to_value).collect()),
-        RespValue::BulkString(bytes) => {
-            let string = String::from_utf8(bytes).unwrap();
+impl PlayerPatch {
+    /// Converts the patch into the hash fields to set and delete.
+    pub(crate) fn into_changes(self) -> HashChanges {
+        let mut changes = HashChanges::default();
 
-            if let Ok(v) = string.parse::<u64>() {
-                Value::Number(Number::from(v))
-            } else {
-                Value::String(string)
-            }
-        },
-        RespValue::Error(why) => panic!("{:?}", why),
-        RespValue::Integer(integer) => Value::Number(Number::from(integer)),
-        RespValue::SimpleString(string) => Value::String(string),
-    }
-}
+        if let Some(mode) = self.loop_mode {
+            changes.set("loop_mode", &mode);
+        }
 
+        match self.node {
+            Some(Some(node)) => changes.set("node", &node),
+            Some(None) => changes.delete("node"),
+            None => {},
+        }
 
-macro from_resp_impls($($struct:ident,)+) {
-    $(
-        impl FromResp for $struct {
-            fn from_resp_int(resp: RespValue) -> Result<Self, RedisError> {
-                convert(resp)
-            }
+        match self.track {
+            Some(Some(track)) => changes.set("track", &track),
+            Some(None) => changes.delete("track"),
+            None => {},
         }
-    )+
-}
 
-from_resp_impls![
-    Guild,
-    GuildChannel,
-    Member,
-    PermissionOverwrite,
-    Role,
-    User,
-    VoiceState,
-];
+        changes
+    }
+}
 
 #[cfg(test)]
 mod tests {
+    use crate::hash::ToHashChanges;
     use redis_async::resp::{FromResp, RespValue};
     use super::*;
 
+    #[test]
+    fn test_emoji() {
+        let value = RespValue::Array(vec![
+            RespValue::BulkString(b"animated".to_vec()),
+            RespValue::BulkString(b"1".to_vec()),
+            RespValue::BulkString(b"id".to_vec()),
+            RespValue::BulkString(b"381880193251409931".to_vec()),
+            RespValue::BulkString(b"name".to_vec()),
+            RespValue::BulkString(b"dab".to_vec()),
+        ]);
+
+        let emoji = Emoji::from_resp(value).unwrap();
+        assert!(emoji.animated);
+        assert_eq!(emoji.id, 381880193251409931);
+        assert!(emoji.roles.is_empty());
+    }
+
+    #[test]
+    fn test_guild_parts_keys() {
+        assert!(GuildParts::empty().keys(1).is_empty());
+        assert_eq!(GuildParts::all().keys(1).len(), 6);
+        assert_eq!(
+            (GuildParts::ROLES | GuildParts::CHANNELS).keys(1),
+            vec![("channels", "g:1:c".to_owned()), ("roles", "g:1:r".to_owned())],
+        );
+    }
+
+    #[test]
+    fn test_guild_without_parts() {
+        let value = RespValue::Array(vec![
+            RespValue::BulkString(b"name".to_vec()),
+            RespValue::BulkString(b"a guild".to_vec()),
+            RespValue::BulkString(b"owner_id".to_vec()),
+            RespValue::BulkString(b"5".to_vec()),
+            RespValue::BulkString(b"region".to_vec()),
+            RespValue::BulkString(b"us-west".to_vec()),
+        ]);
+
+        let guild = Guild::from_resp(value).unwrap();
+        assert_eq!(guild.owner_id, 5);
+        assert!(guild.members.is_empty());
+    }
+
+    #[test]
+    fn test_guild_settings_defaults() {
+        let settings = GuildSettings::from_resp(RespValue::Array(vec![])).unwrap();
+        assert_eq!(settings, GuildSettings::default());
+
+        let value = RespValue::Array(vec![
+            RespValue::BulkString(b"dj_role_id".to_vec()),
+            RespValue::BulkString(b"381880193251409931".to_vec()),
+            RespValue::BulkString(b"prefix".to_vec()),
+            RespValue::BulkString(b"1".to_vec()),
+        ]);
+
+        let settings = GuildSettings::from_resp(value).unwrap();
+        assert_eq!(settings.dj_role_id, Some(381880193251409931));
+        assert_eq!(settings.prefix, "1");
+        assert_eq!(settings.default_volume, 100);
+    }
+
+    #[test]
+    fn test_guild_settings_patch() {
+        let patch = GuildSettingsPatch {
+            dj_role_id: Some(None),
+            default_volume: Some(50),
+            prefix: Some(">".to_owned()),
+            ..GuildSettingsPatch::default()
+        };
+
+        let changes = patch.into_changes();
+        assert_eq!(changes.set, vec![
+            RespValue::from("default_volume"),
+            RespValue::from(50usize),
+            RespValue::from("prefix"),
+            RespValue::from(">".to_owned()),
+        ]);
+        assert_eq!(changes.delete, vec!["dj_role_id"]);
+    }
+
+    #[test]
+    fn test_player_patch() {
+        let patch = PlayerPatch {
+            loop_mode: Some(LoopMode::Song),
+            track: Some(None),
+            ..PlayerPatch::default()
+        };
+
+        let changes = patch.into_changes();
+        assert_eq!(changes.set, vec![
+            RespValue::from("loop_mode"),
+            RespValue::from("LS".to_owned()),
+        ]);
+        assert_eq!(changes.delete, vec!["track"]);
+    }
+
+    #[test]
+    fn test_member_with_user() {
+        let member = RespValue::Array(vec![
+            RespValue::BulkString(b"deaf".to_vec()),
+            RespValue::BulkString(b"0".to_vec()),
+            RespValue::BulkString(b"mute".to_vec()),
+            RespValue::BulkString(b"1".to_vec()),
+            RespValue::BulkString(b"nick".to_vec()),
+            RespValue::BulkString(b"dab".to_vec()),
+            RespValue::BulkString(b"roles".to_vec()),
+            RespValue::Array(vec![RespValue::BulkString(b"6".to_vec())]),
+            RespValue::BulkString(b"user_id".to_vec()),
+            RespValue::BulkString(b"5".to_vec()),
+        ]);
+        let user = RespValue::Array(vec![
+            RespValue::BulkString(b"bot".to_vec()),
+            RespValue::BulkString(b"1".to_vec()),
+            RespValue::BulkString(b"discriminator".to_vec()),
+            RespValue::BulkString(b"0001".to_vec()),
+            RespValue::BulkString(b"id".to_vec()),
+            RespValue::BulkString(b"5".to_vec()),
+            RespValue::BulkString(b"name".to_vec()),
+            RespValue::BulkString(b"1337".to_vec()),
+        ]);
+
+        let member = Member::from_resp_with_user(member, user).unwrap();
+        assert!(!member.deaf);
+        assert_eq!(member.roles, vec![6]);
+        assert!(member.user.bot);
+        assert_eq!(member.user.discriminator, 1);
+        assert_eq!(member.user.name, "1337");
+    }
+
+    #[test]
+    fn test_member_to_resp_hash() {
+        let member = Member {
+            deaf: true,
+            joined_at: None,
+            mute: false,
+            nick: None,
+            roles: vec![6],
+            user: User {
+                bot: false,
+                discriminator: 1,
+                id: 5,
+                name: "dab".to_owned(),
+            },
+            user_id: 5,
+        };
+
+        let changes = member.to_resp_hash().unwrap();
+        assert_eq!(changes.set, vec![
+            RespValue::from("deaf"),
+            RespValue::from(1usize),
+            RespValue::from("mute"),
+            RespValue::from(0usize),
+            RespValue::from("user_id"),
+            RespValue::from(5usize),
+        ]);
+        assert_eq!(changes.delete, vec!["nick"]);
+    }
+
+    #[test]
+    fn test_message_hash_content() {
+        assert_eq!(Message::hash_content(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(Message::hash_content("a"), 0xaf63_dc4c_8601_ec8c);
+        assert_ne!(Message::hash_content("now playing"), Message::hash_content("now playing!"));
+    }
+
+    #[test]
+    fn test_presence() {
+        let value = RespValue::Array(vec![
+            RespValue::BulkString(b"status".to_vec()),
+            RespValue::BulkString(b"online".to_vec()),
+        ]);
+
+        let presence = Presence::from_resp(value).unwrap();
+        assert!(presence.activity_name.is_none());
+        assert!(!presence.is_listening());
+
+        let value = RespValue::Array(vec![
+            RespValue::BulkString(b"activity_kind".to_vec()),
+            RespValue::BulkString(b"2".to_vec()),
+            RespValue::BulkString(b"activity_name".to_vec()),
+            RespValue::BulkString(b"1999".to_vec()),
+            RespValue::BulkString(b"status".to_vec()),
+            RespValue::BulkString(b"dnd".to_vec()),
+        ]);
+
+        let presence = Presence::from_resp(value).unwrap();
+        assert_eq!(presence.activity_name.as_ref().map(AsRef::as_ref), Some("1999"));
+        assert!(presence.is_listening());
+    }
+
     #[test]
     fn test_role() {
         let value = RespValue::Array(vec![
+            RespValue::BulkString(b"colour".to_vec()),
+            RespValue::BulkString(b"0".to_vec()),
             RespValue::BulkString(b"name".to_vec()),
             RespValue::BulkString(b"test".to_vec()),
             RespValue::BulkString(b"permissions".to_vec()),
@@ -213,13 +811,33 @@ mod tests {
         assert!(Role::from_resp(value).is_ok());
 
         let value = RespValue::Array(vec![
+            RespValue::BulkString(b"colour".to_vec()),
+            RespValue::BulkString(b"0".to_vec()),
             RespValue::BulkString(b"name".to_vec()),
             RespValue::BulkString(b"0123456".to_vec()),
             RespValue::BulkString(b"permissions".to_vec()),
             RespValue::BulkString(b"8".to_vec()),
         ]);
 
-        assert!(Role::from_resp(value).is_ok());
+        assert_eq!(Role::from_resp(value).unwrap().name, "0123456");
+    }
+
+    #[test]
+    fn test_role_resp_hash_round_trip() {
+        let role = Role {
+            colour: 0x00ff_00ff,
+            name: "007".to_owned(),
+            permissions: Permissions::ADMINISTRATOR,
+        };
+
+        let changes = role.to_hash_changes();
+        assert!(changes.delete.is_empty());
+        assert_eq!(role.to_resp_hash().unwrap(), changes);
+
+        let role = Role::from_resp_hash(RespValue::Array(changes.set)).unwrap();
+        assert_eq!(role.colour, 0x00ff_00ff);
+        assert_eq!(role.name, "007");
+        assert_eq!(role.permissions, Permissions::ADMINISTRATOR);
     }
 
     #[test]
@@ -233,7 +851,51 @@ mod tests {
             RespValue::BulkString(b"450d2eedffbdad13".to_vec()),
         ]);
 
-        assert!(VoiceState::from_resp(value).is_ok());
+        let state = VoiceState::from_resp(value).unwrap();
+        assert!(state.connection_info(1, 2).is_none());
+    }
+
+    #[test]
+    fn test_voice_state_connection_info() {
+        let value = RespValue::Array(vec![
+            RespValue::BulkString(b"channel_id".to_vec()),
+            RespValue::BulkString(b"500000000000000000".to_vec()),
+            RespValue::BulkString(b"endpoint".to_vec()),
+            RespValue::BulkString(b"us-east1.discord.gg:80".to_vec()),
+            RespValue::BulkString(b"session_id".to_vec()),
+            RespValue::BulkString(b"946f395aa3c194fda2aa7baa2e402d2b".to_vec()),
+            RespValue::BulkString(b"token".to_vec()),
+            RespValue::BulkString(b"450d2eedffbdad13".to_vec()),
+        ]);
+
+        let state = VoiceState::from_resp(value).unwrap();
+        assert_eq!(state.connection_info(1, 2), Some(VoiceConnectionInfo {
+            channel_id: 500000000000000000,
+            endpoint: "us-east1.discord.gg:80".to_owned(),
+            guild_id: 1,
+            session_id: "946f395aa3c194fda2aa7baa2e402d2b".to_owned(),
+            token: "450d2eedffbdad13".to_owned(),
+            user_id: 2,
+        }));
+    }
+
+    #[test]
+    fn test_voice_state_deafened() {
+        let value = RespValue::Array(vec![
+            RespValue::BulkString(b"channel_id".to_vec()),
+            RespValue::BulkString(b"500000000000000000".to_vec()),
+            RespValue::BulkString(b"deaf".to_vec()),
+            RespValue::BulkString(b"0".to_vec()),
+            RespValue::BulkString(b"self_deaf".to_vec()),
+            RespValue::BulkString(b"1".to_vec()),
+            RespValue::BulkString(b"session_id".to_vec()),
+            RespValue::BulkString(b"946f395aa3c194fda2aa7baa2e402d2b".to_vec()),
+        ]);
+
+        let state = VoiceState::from_resp(value).unwrap();
+        assert!(!state.deaf);
+        assert!(state.self_deaf);
+        assert!(state.is_deafened());
     }
 
     #[test]
@@ -254,7 +916,32 @@ mod tests {
             RespValue::BulkString(b"11111111111111111111111111111111".to_vec()),
         ]);
 
-        assert!(VoiceState::from_resp(value).is_ok());
+        let state = VoiceState::from_resp(value).unwrap();
+        assert_eq!(state.session_id, "11111111111111111111111111111111");
+    }
+
+    #[test]
+    fn test_guild_inconsistencies_is_empty() {
+        let mut report = GuildInconsistencies::default();
+        assert!(report.is_empty());
+
+        report.dangling_member_roles.push((1, 2));
+        assert!(!report.is_empty());
+    }
+
+    #[test]
+    fn test_skip_votes() {
+        let votes = SkipVotes { listeners: 5, votes: 2 };
+        assert_eq!(votes.required(0.5), 3);
+        assert!(!votes.passes(0.5));
+        assert!(votes.passes(0.4));
+
+        let votes = SkipVotes { listeners: 0, votes: 0 };
+        assert_eq!(votes.required(0.5), 1);
+        assert!(!votes.passes(0.5));
+
+        let votes = SkipVotes { listeners: 1, votes: 1 };
+        assert!(votes.passes(1.0));
     }
 
     #[test]
@@ -0,0 +1,216 @@
+use crate::{
+    cache::Cache,
+    error::Result,
+    gen,
+};
+use redis_async::resp::RespValue;
+use std::{
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+/// Increments the window's counter, starting its expiry if it doesn't have one.
+///
+/// Checking the TTL rather than the count means a counter that somehow lost its
+/// expiry can't limit a user forever.
+///
+/// Returns the count and the window's remaining time in milliseconds.
+const FIXED_WINDOW_SCRIPT: &str = r#"
+local count = redis.call('INCR', KEYS[1])
+if redis.call('PTTL', KEYS[1]) < 0 then
+    redis.call('PEXPIRE', KEYS[1], ARGV[1])
+end
+return {count, redis.call('PTTL', KEYS[1])}
+"#;
+
+/// Drops hits older than the window and records a new hit if there's room.
+///
+/// The time comes from Redis rather than the caller, so processes with skewed
+/// clocks share one view of the window. `ARGV[3]` makes the hit's member unique
+/// among hits in the same millisecond.
+///
+/// Returns whether the hit was recorded, the number of hits in the window, and
+/// the time in milliseconds until the oldest hit leaves the window.
+const SLIDING_WINDOW_SCRIPT: &str = r#"
+redis.replicate_commands()
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local window = tonumber(ARGV[1])
+local limit = tonumber(ARGV[2])
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now - window)
+local count = redis.call('ZCARD', KEYS[1])
+local allowed = 0
+if count < limit then
+    redis.call('ZADD', KEYS[1], now, now .. ':' .. ARGV[3])
+    count = count + 1
+    allowed = 1
+end
+redis.call('PEXPIRE', KEYS[1], window)
+local oldest = redis.call('ZRANGE', KEYS[1], 0, 0, 'WITHSCORES')
+local reset = 0
+if oldest[2] then
+    reset = tonumber(oldest[2]) + window - now
+end
+return {allowed, count, reset}
+"#;
+
+static HIT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The result of checking a rate limit.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RateLimit {
+    /// Whether the hit was within the limit.
+    pub allowed: bool,
+    /// The number of hits remaining in the window.
+    pub remaining: u64,
+    /// The time until the window resets, or for sliding windows until the
+    /// oldest hit leaves the window.
+    pub reset_after: Duration,
+}
+
+impl RateLimit {
+    fn from_fixed_window(count: i64, limit: u64, ttl_ms: i64) -> Self {
+        let count = count.max(0) as u64;
+
+        Self {
+            allowed: count <= limit,
+            remaining: limit.saturating_sub(count),
+            reset_after: Duration::from_millis(ttl_ms.max(0) as u64),
+        }
+    }
+
+    fn from_sliding_window(
+        allowed: i64,
+        count: i64,
+        limit: u64,
+        reset_ms: i64,
+    ) -> Self {
+        Self {
+            allowed: allowed == 1,
+            remaining: limit.saturating_sub(count.max(0) as u64),
+            reset_after: Duration::from_millis(reset_ms.max(0) as u64),
+        }
+    }
+}
+
+/// Rate limiting.
+///
+/// Limits are keyed by guild, user, and command name, and are shared between
+/// every process using the same Redis instance.
+impl Cache {
+    /// Records a hit against a fixed-window rate limit of `limit` hits per
+    /// `window`.
+    ///
+    /// The window starts at the first hit and resets all at once when it
+    /// expires.
+    pub async fn ratelimit_fixed<'a>(
+        &'a self,
+        guild_id: u64,
+        user_id: u64,
+        command: &'a str,
+        limit: u64,
+        window: Duration,
+    ) -> Result<RateLimit> {
+        let values: Vec<i64> = await!(self.inner().eval(
+            FIXED_WINDOW_SCRIPT,
+            vec![gen::ratelimit_fixed(guild_id, user_id, command)],
+            vec![RespValue::from(millis(window) as usize)],
+        ))?;
+
+        Ok(RateLimit::from_fixed_window(*values.get(0)?, limit, *values.get(1)?))
+    }
+
+    /// Records a hit against a sliding-window rate limit of `limit` hits in
+    /// any `window`.
+    ///
+    /// Hits that are over the limit aren't recorded, so they don't extend how
+    /// long the user is limited for.
+    pub async fn ratelimit_sliding<'a>(
+        &'a self,
+        guild_id: u64,
+        user_id: u64,
+        command: &'a str,
+        limit: u64,
+        window: Duration,
+    ) -> Result<RateLimit> {
+        let member = format!(
+            "{}:{}",
+            process::id(),
+            HIT_COUNTER.fetch_add(1, Ordering::Relaxed),
+        );
+
+        let values: Vec<i64> = await!(self.inner().eval(
+            SLIDING_WINDOW_SCRIPT,
+            vec![gen::ratelimit_sliding(guild_id, user_id, command)],
+            vec![
+                RespValue::from(millis(window) as usize),
+                RespValue::from(limit as usize),
+                RespValue::from(member),
+            ],
+        ))?;
+
+        Ok(RateLimit::from_sliding_window(
+            *values.get(0)?,
+            *values.get(1)?,
+            limit,
+            *values.get(2)?,
+        ))
+    }
+
+    /// Clears a user's fixed-window and sliding-window rate limits for a
+    /// command.
+    pub async fn reset_ratelimit<'a>(
+        &'a self,
+        guild_id: u64,
+        user_id: u64,
+        command: &'a str,
+    ) -> Result<()> {
+        await!(self.inner().delm(vec![
+            gen::ratelimit_fixed(guild_id, user_id, command),
+            gen::ratelimit_sliding(guild_id, user_id, command),
+        ]))
+    }
+}
+
+pub(crate) fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::RateLimit;
+
+    #[test]
+    fn test_fixed_window() {
+        let limit = RateLimit::from_fixed_window(1, 3, 60000);
+        assert!(limit.allowed);
+        assert_eq!(limit.remaining, 2);
+        assert_eq!(limit.reset_after, Duration::from_secs(60));
+
+        let limit = RateLimit::from_fixed_window(4, 3, 1500);
+        assert!(!limit.allowed);
+        assert_eq!(limit.remaining, 0);
+
+        let limit = RateLimit::from_fixed_window(1, 3, -1);
+        assert_eq!(limit.reset_after, Duration::from_millis(0));
+    }
+
+    #[test]
+    fn test_sliding_window() {
+        let limit = RateLimit::from_sliding_window(1, 3, 3, 250);
+        assert!(limit.allowed);
+        assert_eq!(limit.remaining, 0);
+        assert_eq!(limit.reset_after, Duration::from_millis(250));
+
+        let limit = RateLimit::from_sliding_window(0, 3, 3, 100);
+        assert!(!limit.allowed);
+    }
+
+    #[test]
+    fn test_millis() {
+        assert_eq!(super::millis(Duration::from_millis(1500)), 1500);
+        assert_eq!(super::millis(Duration::from_secs(2)), 2000);
+    }
+}
@@ -0,0 +1,219 @@
+use crate::{
+    cache::Cache,
+    error::Result,
+    gen,
+};
+use redis_async::resp::RespValue;
+
+/// Replaces a member's entries in the guild's search index.
+///
+/// `KEYS[1]` is the guild's index and `KEYS[2]` is the Set of the member's
+/// current entries. Each argument is a new entry.
+const REINDEX_SCRIPT: &str = r#"
+local old = redis.call('SMEMBERS', KEYS[2])
+if #old > 0 then
+    redis.call('ZREM', KEYS[1], unpack(old))
+end
+redis.call('DEL', KEYS[2])
+for i = 1, #ARGV do
+    redis.call('ZADD', KEYS[1], 0, ARGV[i])
+    redis.call('SADD', KEYS[2], ARGV[i])
+end
+return #ARGV
+"#;
+
+/// Separates the searchable name from the user ID in an index entry.
+const SEPARATOR: u8 = 0;
+
+/// Creates the search index entries for a member.
+///
+/// Usernames are indexed with their discriminator, as `name#0001`, so that
+/// searching by username prefix and resolving an exact tag use the same index.
+/// Names are lowercased so that searches are case-insensitive.
+fn entries(
+    user_id: u64,
+    name: &str,
+    discriminator: u16,
+    nick: Option<&str>,
+) -> Vec<Vec<u8>> {
+    let mut names = vec![format!("{}#{:04}", name.to_lowercase(), discriminator)];
+
+    if let Some(nick) = nick {
+        names.push(nick.to_lowercase());
+    }
+
+    names.into_iter().map(|name| entry(&name, user_id)).collect()
+}
+
+fn entry(name: &str, user_id: u64) -> Vec<u8> {
+    let mut entry = name.as_bytes().to_vec();
+    entry.push(SEPARATOR);
+    entry.extend_from_slice(user_id.to_string().as_bytes());
+
+    entry
+}
+
+/// Parses the user ID out of an index entry.
+fn parse_entry(entry: &[u8]) -> Option<u64> {
+    let pos = entry.iter().rposition(|byte| *byte == SEPARATOR)?;
+
+    std::str::from_utf8(&entry[pos + 1..]).ok()?.parse().ok()
+}
+
+/// Creates the inclusive range of index entries starting with a prefix.
+///
+/// `0xFF` never appears in UTF-8, so it sorts after every entry with the
+/// prefix.
+fn prefix_range(prefix: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut min = vec![b'['];
+    min.extend_from_slice(prefix);
+
+    let mut max = min.clone();
+    max.push(0xFF);
+
+    (min, max)
+}
+
+/// Member search.
+///
+/// Each guild has a sorted Set of lowercased usernames (with discriminators)
+/// and nicknames, which is kept up to date when members are upserted.
+impl Cache {
+    /// Searches a guild's members by the start of their username or nickname,
+    /// case-insensitively.
+    ///
+    /// Returns up to `limit` user IDs, ordered by the matching name.
+    pub async fn search_members<'a>(
+        &'a self,
+        guild_id: u64,
+        prefix: &'a str,
+        limit: usize,
+    ) -> Result<Vec<u64>> {
+        if limit == 0 {
+            return Ok(vec![]);
+        }
+
+        let prefix = prefix.to_lowercase();
+
+        // A member can match by both username and nickname, so get extra
+        // entries to make up for duplicates.
+        let entries = await!(self.search_entries(
+            guild_id,
+            prefix.as_bytes(),
+            limit * 2,
+        ))?;
+
+        let mut ids = Vec::with_capacity(limit);
+
+        for id in entries.iter().filter_map(|entry| parse_entry(entry)) {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+
+            if ids.len() == limit {
+                break;
+            }
+        }
+
+        Ok(ids)
+    }
+
+    /// Resolves a `name#discriminator` tag to the ID of a guild member,
+    /// case-insensitively.
+    pub async fn resolve_member_tag<'a>(
+        &'a self,
+        guild_id: u64,
+        tag: &'a str,
+    ) -> Result<Option<u64>> {
+        let mut prefix = tag.to_lowercase().into_bytes();
+        prefix.push(SEPARATOR);
+
+        let entries = await!(self.search_entries(guild_id, &prefix, 1))?;
+
+        Ok(entries.first().and_then(|entry| parse_entry(entry)))
+    }
+
+    /// Replaces a member's entries in their guild's search index.
+    pub(crate) fn index_member(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        name: &str,
+        discriminator: u16,
+        nick: Option<&str>,
+    ) {
+        let entries = entries(user_id, name, discriminator, nick)
+            .into_iter()
+            .map(RespValue::from)
+            .collect();
+
+        self.inner().eval_sync(
+            REINDEX_SCRIPT,
+            vec![
+                gen::guild_member_search(guild_id),
+                gen::member_search_entries(guild_id, user_id),
+            ],
+            entries,
+        );
+    }
+
+    /// Removes a member's entries from their guild's search index.
+    pub(crate) fn unindex_member(&self, guild_id: u64, user_id: u64) {
+        self.inner().eval_sync(
+            REINDEX_SCRIPT,
+            vec![
+                gen::guild_member_search(guild_id),
+                gen::member_search_entries(guild_id, user_id),
+            ],
+            vec![],
+        );
+    }
+
+    async fn search_entries<'a>(
+        &'a self,
+        guild_id: u64,
+        prefix: &'a [u8],
+        limit: usize,
+    ) -> Result<Vec<Vec<u8>>> {
+        let (min, max) = prefix_range(prefix);
+
+        await!(self.inner().send(resp_array![
+            "ZRANGEBYLEX",
+            gen::guild_member_search(guild_id),
+            min,
+            max,
+            "LIMIT",
+            0usize,
+            limit
+        ]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_entries() {
+        assert_eq!(super::entries(5, "Zeyla", 1, None), vec![
+            b"zeyla#0001\x005".to_vec(),
+        ]);
+        assert_eq!(super::entries(5, "Zeyla", 1234, Some("DAB")), vec![
+            b"zeyla#1234\x005".to_vec(),
+            b"dab\x005".to_vec(),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_entry() {
+        assert_eq!(super::parse_entry(b"zeyla#0001\x00114941315417899012"), Some(114941315417899012));
+        assert_eq!(super::parse_entry(b"no separator"), None);
+        assert_eq!(super::parse_entry(b"bad id\x00abc"), None);
+    }
+
+    #[test]
+    fn test_prefix_range() {
+        let (min, max) = super::prefix_range(b"dab");
+
+        assert_eq!(min, b"[dab".to_vec());
+        assert_eq!(max, b"[dab\xff".to_vec());
+    }
+}
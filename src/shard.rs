@@ -0,0 +1,28 @@
+use std::num::NonZeroU64;
+
+/// Calculates the ID of the shard that a guild is on, given the total number
+/// of shards.
+///
+/// This is Discord's sharding formula: `(guild_id >> 22) % shard_count`.
+pub fn shard_id(guild_id: u64, shard_count: NonZeroU64) -> u64 {
+    (guild_id >> 22) % shard_count.get()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU64;
+
+    fn shard_id(guild_id: u64, shard_count: u64) -> u64 {
+        super::shard_id(guild_id, NonZeroU64::new(shard_count).unwrap())
+    }
+
+    #[test]
+    fn test_shard_id() {
+        assert_eq!(shard_id(1, 1), 0);
+        assert_eq!(shard_id(381880193251409931, 1), 0);
+        assert_eq!(shard_id(381880193251409931, 3), 2);
+        assert_eq!(shard_id(381880193251409931, 16), 6);
+        assert_eq!(shard_id(272410239947767808, 2), 1);
+        assert_eq!(shard_id(272410239947767808, 8), 5);
+    }
+}
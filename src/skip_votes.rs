@@ -0,0 +1,163 @@
+use crate::{
+    cache::Cache,
+    error::Result,
+    gen,
+    model::{PlayerPatch, SkipVotes},
+};
+use redis_async::resp::RespValue;
+
+/// Applies changes to a guild's player hash, resetting its skip votes if the
+/// current track changed.
+///
+/// `KEYS[1]` is the guild's player hash and `KEYS[2]` its skip votes.
+/// `ARGV[1]` is the number of fields to delete, followed by their names and
+/// then by the field names and values to set.
+const SET_PLAYER_SCRIPT: &str = r#"
+local old = redis.call('HGET', KEYS[1], 'track')
+local deletes = tonumber(ARGV[1])
+if deletes > 0 then
+    redis.call('HDEL', KEYS[1], unpack(ARGV, 2, deletes + 1))
+end
+if #ARGV > deletes + 1 then
+    redis.call('HMSET', KEYS[1], unpack(ARGV, deletes + 2))
+end
+if redis.call('HGET', KEYS[1], 'track') ~= old then
+    redis.call('DEL', KEYS[2])
+end
+return 0
+"#;
+
+/// Adds a skip vote if the track being voted on is the current one.
+///
+/// `KEYS[1]` is the guild's player hash and `KEYS[2]` its skip votes.
+/// `ARGV[1]` is the track being voted on and `ARGV[2]` the voter's ID.
+///
+/// Returns -1 if the track isn't the current one, and otherwise the number of
+/// votes added.
+const ADD_VOTE_SCRIPT: &str = r#"
+if redis.call('HGET', KEYS[1], 'track') ~= ARGV[1] then
+    return -1
+end
+return redis.call('SADD', KEYS[2], ARGV[2])
+"#;
+
+/// Skip votes.
+///
+/// Votes are stored per guild and belong to the guild's current track, which
+/// is stored in the `track` field of the guild's player hash. Setting a
+/// different current track resets the votes.
+impl Cache {
+    /// Gets the current track of a guild's player.
+    pub async fn get_current_track(
+        &self,
+        guild_id: u64,
+    ) -> Result<Option<String>> {
+        await!(self.inner().hget(gen::guild_player(guild_id), "track".to_owned()))
+    }
+
+    /// Sets the current track of a guild's player.
+    ///
+    /// If the track differs from the previous one then the guild's skip votes
+    /// are reset, atomically with the change. Passing `None` clears the
+    /// current track.
+    pub async fn set_current_track(
+        &self,
+        guild_id: u64,
+        track: Option<String>,
+    ) -> Result<()> {
+        let changes = PlayerPatch {
+            track: Some(track),
+            ..PlayerPatch::default()
+        }.into_changes();
+
+        let mut args = Vec::with_capacity(1 + changes.delete.len() + changes.set.len());
+        args.push(RespValue::from(changes.delete.len()));
+        args.extend(changes.delete.into_iter().map(RespValue::from));
+        args.extend(changes.set);
+
+        await!(self.inner().eval::<i64>(
+            SET_PLAYER_SCRIPT,
+            vec![gen::guild_player(guild_id), gen::guild_skip_votes(guild_id)],
+            args,
+        ))?;
+
+        Ok(())
+    }
+
+    /// Adds a vote to skip a guild's current track.
+    ///
+    /// The track being voted on must be given so that a vote sent just after
+    /// the track changes isn't counted towards the next one. The check and
+    /// the vote happen atomically.
+    ///
+    /// Returns `None` if the track isn't the current one, otherwise whether
+    /// the vote is new.
+    pub async fn add_skip_vote<'a>(
+        &'a self,
+        guild_id: u64,
+        track: &'a str,
+        user_id: u64,
+    ) -> Result<Option<bool>> {
+        let added: i64 = await!(self.inner().eval(
+            ADD_VOTE_SCRIPT,
+            vec![gen::guild_player(guild_id), gen::guild_skip_votes(guild_id)],
+            vec![RespValue::from(track), RespValue::from(user_id.to_string())],
+        ))?;
+
+        Ok(if added < 0 { None } else { Some(added > 0) })
+    }
+
+    /// Removes a vote to skip a guild's current track.
+    pub async fn remove_skip_vote(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+    ) -> Result<()> {
+        await!(self.inner().srem(
+            gen::guild_skip_votes(guild_id),
+            vec![user_id as usize],
+        ))?;
+
+        Ok(())
+    }
+
+    /// Removes all votes to skip a guild's current track.
+    pub async fn reset_skip_votes(&self, guild_id: u64) -> Result<()> {
+        await!(self.inner().del(gen::guild_skip_votes(guild_id)))
+    }
+
+    /// Tallies the votes to skip a guild's current track against the
+    /// listeners in the bot's voice channel.
+    ///
+    /// Only votes from members who are still listening in the channel are
+    /// counted.
+    pub async fn skip_vote_tally(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> Result<SkipVotes> {
+        let voters: Vec<String> = await!(self.inner().sinter(vec![
+            gen::guild_skip_votes(guild_id),
+            gen::channel_listeners(channel_id),
+        ]))?;
+        let listeners = await!(self.channel_listener_count(channel_id))?;
+
+        Ok(SkipVotes {
+            listeners,
+            votes: voters.len() as u64,
+        })
+    }
+
+    /// Whether enough listeners in the bot's voice channel have voted to skip
+    /// a guild's current track, given the ratio of listeners that must vote.
+    pub async fn skip_vote_passes(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        ratio: f64,
+    ) -> Result<bool> {
+        let tally = await!(self.skip_vote_tally(guild_id, channel_id))?;
+
+        Ok(tally.passes(ratio))
+    }
+}
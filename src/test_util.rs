@@ -0,0 +1,1449 @@
+//! Utilities for testing code that uses the cache, enabled by the `test-util`
+//! feature.
+//!
+//! [`FakeRedis`] is an in-process TCP server speaking enough of RESP to run the
+//! cache end to end through `redis_async` without a real Redis instance.
+//!
+//! [`FakeRedis`]: struct.FakeRedis.html
+
+mod script;
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// A value stored in a [`FakeRedis`] instance.
+///
+/// [`FakeRedis`]: struct.FakeRedis.html
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Hash(BTreeMap<Vec<u8>, Vec<u8>>),
+    List(VecDeque<Vec<u8>>),
+    Set(BTreeSet<Vec<u8>>),
+    /// A sorted Set's members and their scores.
+    SortedSet(BTreeMap<Vec<u8>, f64>),
+    String(Vec<u8>),
+}
+
+/// A reply to a command.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Reply {
+    Array(Vec<Reply>),
+    Bulk(Option<Vec<u8>>),
+    Error(String),
+    Integer(i64),
+    Simple(&'static str),
+}
+
+impl Reply {
+    fn wrong_type() -> Self {
+        Reply::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_owned())
+    }
+
+    fn bulks<It: IntoIterator<Item = Vec<u8>>>(values: It) -> Self {
+        Reply::Array(values.into_iter().map(|v| Reply::Bulk(Some(v))).collect())
+    }
+
+    fn not_integer() -> Self {
+        Reply::Error("ERR value is not an integer or out of range".to_owned())
+    }
+
+    /// A reply to a scan, with every item in one batch and a cursor of 0.
+    fn scan<It: IntoIterator<Item = Vec<u8>>>(items: It) -> Self {
+        Reply::Array(vec![Reply::Bulk(Some(b"0".to_vec())), Reply::bulks(items)])
+    }
+
+    fn write_to(&self, buf: &mut Vec<u8>) {
+        match self {
+            Reply::Array(replies) => {
+                buf.extend_from_slice(format!("*{}\r\n", replies.len()).as_bytes());
+
+                for reply in replies {
+                    reply.write_to(buf);
+                }
+            },
+            Reply::Bulk(Some(bytes)) => {
+                buf.extend_from_slice(format!("${}\r\n", bytes.len()).as_bytes());
+                buf.extend_from_slice(bytes);
+                buf.extend_from_slice(b"\r\n");
+            },
+            Reply::Bulk(None) => buf.extend_from_slice(b"$-1\r\n"),
+            Reply::Error(why) => buf.extend_from_slice(format!("-{}\r\n", why).as_bytes()),
+            Reply::Integer(int) => buf.extend_from_slice(format!(":{}\r\n", int).as_bytes()),
+            Reply::Simple(string) => buf.extend_from_slice(format!("+{}\r\n", string).as_bytes()),
+        }
+    }
+}
+
+/// The data held by a [`FakeRedis`] instance.
+///
+/// [`FakeRedis`]: struct.FakeRedis.html
+#[derive(Clone, Debug, Default)]
+struct Store {
+    /// The number of replies left to delay for each command, and how long to
+    /// delay them by.
+    delays: HashMap<String, (usize, Duration)>,
+    /// When keys with a TTL expire.
+    expiries: HashMap<Vec<u8>, Instant>,
+    /// Messages published to each channel, which are never delivered.
+    published: HashMap<Vec<u8>, Vec<Vec<u8>>>,
+    /// The number of times each command has been received.
+    received: HashMap<String, usize>,
+    values: HashMap<Vec<u8>, Value>,
+}
+
+impl Store {
+    fn execute(&mut self, args: Vec<Vec<u8>>) -> Reply {
+        let mut args = args.into_iter();
+        let command = match args.next() {
+            Some(command) => String::from_utf8_lossy(&command).to_uppercase(),
+            None => return Reply::Error("ERR empty command".to_owned()),
+        };
+        let args = args.collect::<Vec<_>>();
+
+        self.remove_expired();
+
+        match (&*command, args.len()) {
+            ("DEL", n) if n >= 1 => self.del(args),
+            ("EVAL", n) if n >= 2 => self.eval(args),
+            ("EXISTS", n) if n >= 1 => self.exists(args),
+            ("EXPIRE", 2) => self.expire(&args[0], &args[1], 1000),
+            ("GET", 1) => self.get(&args[0]),
+            ("HDEL", n) if n >= 2 => self.hdel(args),
+            ("HGET", 2) => self.hget(&args[0], &args[1]),
+            ("HGETALL", 1) => self.hgetall(&args[0]),
+            ("HMGET", n) if n >= 2 => self.hmget(args),
+            ("HMSET", n) if n >= 3 && n % 2 == 1 => self.hmset(args),
+            ("HSCAN", n) if n >= 2 => self.hscan(&args[0]),
+            ("HSET", n) if n >= 3 && n % 2 == 1 => self.hset(args),
+            ("INCR", 1) => self.incr(&args[0]),
+            ("LINDEX", 2) => self.lindex(&args[0], &args[1]),
+            ("LLEN", 1) => self.llen(&args[0]),
+            ("LPUSH", n) if n >= 2 => self.push(args, true),
+            ("LRANGE", 3) => self.lrange(&args[0], &args[1], &args[2]),
+            ("LREM", 3) => self.lrem(args),
+            ("LSET", 3) => self.lset(args),
+            ("LTRIM", 3) => self.ltrim(&args[0], &args[1], &args[2]),
+            ("MGET", n) if n >= 1 => self.mget(args),
+            ("PEXPIRE", 2) => self.expire(&args[0], &args[1], 1),
+            ("PTTL", 1) => self.pttl(&args[0]),
+            ("PUBLISH", 2) => self.publish(args),
+            ("RPUSH", n) if n >= 2 => self.push(args, false),
+            ("SADD", n) if n >= 2 => self.sadd(args),
+            ("SCAN", n) if n >= 1 => self.scan(args),
+            ("SCARD", 1) => self.scard(&args[0]),
+            ("SET", 2) => self.set(args),
+            ("SINTER", n) if n >= 1 => self.sinter(args),
+            ("SMEMBERS", 1) => self.smembers(&args[0]),
+            ("SREM", n) if n >= 2 => self.srem(args),
+            ("SSCAN", n) if n >= 2 => self.sscan(&args[0]),
+            ("TIME", 0) => self.time(),
+            ("ZADD", n) if n >= 3 && n % 2 == 1 => self.zadd(args),
+            ("ZCARD", 1) => self.zcard(&args[0]),
+            ("ZRANGE", 3) | ("ZRANGE", 4) => self.zrange(args),
+            ("ZRANGEBYLEX", 3) | ("ZRANGEBYLEX", 6) => self.zrangebylex(args),
+            ("ZREM", n) if n >= 2 => self.zrem(args),
+            ("ZREMRANGEBYSCORE", 3) => self.zremrangebyscore(&args[0], &args[1], &args[2]),
+            (command, _) => Reply::Error(format!("ERR unknown command or wrong number of arguments for '{}'", command)),
+        }
+    }
+
+    fn del(&mut self, keys: Vec<Vec<u8>>) -> Reply {
+        let count = keys.iter().filter(|key| self.remove(key)).count();
+
+        Reply::Integer(count as i64)
+    }
+
+    fn eval(&mut self, args: Vec<Vec<u8>>) -> Reply {
+        let mut args = args.into_iter();
+        let script = args.next().unwrap();
+        let count = match args.next().and_then(|count| parse_int(&count)) {
+            Some(count) if count >= 0 => count as usize,
+            _ => return Reply::Error("ERR value is not an integer or out of range".to_owned()),
+        };
+
+        let mut keys = args.collect::<Vec<_>>();
+
+        if count > keys.len() {
+            return Reply::Error("ERR Number of keys can't be greater than number of args".to_owned());
+        }
+
+        let argv = keys.split_off(count);
+
+        script::eval(self, &script, keys, argv)
+    }
+
+    fn exists(&self, keys: Vec<Vec<u8>>) -> Reply {
+        let count = keys.iter().filter(|key| self.values.contains_key(*key)).count();
+
+        Reply::Integer(count as i64)
+    }
+
+    /// Sets a key's TTL, given in units of `millis` milliseconds.
+    fn expire(&mut self, key: &[u8], ttl: &[u8], millis: u64) -> Reply {
+        let ttl = match parse_int(ttl) {
+            Some(ttl) => ttl,
+            None => return Reply::not_integer(),
+        };
+
+        if !self.values.contains_key(key) {
+            return Reply::Integer(0);
+        }
+
+        if ttl <= 0 {
+            self.remove(key);
+        } else {
+            let ttl = Duration::from_millis(ttl as u64 * millis);
+            self.expiries.insert(key.to_vec(), Instant::now() + ttl);
+        }
+
+        Reply::Integer(1)
+    }
+
+    fn get(&self, key: &[u8]) -> Reply {
+        match self.values.get(key) {
+            Some(Value::String(value)) => Reply::Bulk(Some(value.clone())),
+            Some(_) => Reply::wrong_type(),
+            None => Reply::Bulk(None),
+        }
+    }
+
+    fn hdel(&mut self, args: Vec<Vec<u8>>) -> Reply {
+        let mut args = args.into_iter();
+        let key = args.next().unwrap();
+
+        let count = match self.values.get_mut(&key) {
+            Some(Value::Hash(hash)) => args.filter(|field| hash.remove(field).is_some()).count(),
+            Some(_) => return Reply::wrong_type(),
+            None => 0,
+        };
+
+        self.remove_if_empty(&key);
+
+        Reply::Integer(count as i64)
+    }
+
+    fn hget(&self, key: &[u8], field: &[u8]) -> Reply {
+        match self.values.get(key) {
+            Some(Value::Hash(hash)) => Reply::Bulk(hash.get(field).cloned()),
+            Some(_) => Reply::wrong_type(),
+            None => Reply::Bulk(None),
+        }
+    }
+
+    fn hgetall(&self, key: &[u8]) -> Reply {
+        match self.values.get(key) {
+            Some(Value::Hash(hash)) => Reply::bulks(hash.iter().flat_map(|(field, value)| {
+                vec![field.clone(), value.clone()]
+            })),
+            Some(_) => Reply::wrong_type(),
+            None => Reply::Array(vec![]),
+        }
+    }
+
+    fn hmget(&self, args: Vec<Vec<u8>>) -> Reply {
+        let mut args = args.into_iter();
+        let key = args.next().unwrap();
+
+        match self.values.get(&key) {
+            Some(Value::Hash(hash)) => {
+                Reply::Array(args.map(|field| Reply::Bulk(hash.get(&field).cloned())).collect())
+            },
+            Some(_) => Reply::wrong_type(),
+            None => Reply::Array(args.map(|_| Reply::Bulk(None)).collect()),
+        }
+    }
+
+    fn hmset(&mut self, args: Vec<Vec<u8>>) -> Reply {
+        let mut args = args.into_iter();
+        let key = args.next().unwrap();
+
+        let hash = match self.values.entry(key).or_insert_with(|| Value::Hash(BTreeMap::new())) {
+            Value::Hash(hash) => hash,
+            _ => return Reply::wrong_type(),
+        };
+
+        while let (Some(field), Some(value)) = (args.next(), args.next()) {
+            hash.insert(field, value);
+        }
+
+        Reply::Simple("OK")
+    }
+
+    /// Returns every field and value in one batch, ignoring the cursor and
+    /// count.
+    fn hscan(&self, key: &[u8]) -> Reply {
+        match self.values.get(key) {
+            Some(Value::Hash(hash)) => Reply::scan(hash.iter().flat_map(|(field, value)| {
+                vec![field.clone(), value.clone()]
+            })),
+            Some(_) => Reply::wrong_type(),
+            None => Reply::scan(vec![]),
+        }
+    }
+
+    fn hset(&mut self, args: Vec<Vec<u8>>) -> Reply {
+        let mut args = args.into_iter();
+        let key = args.next().unwrap();
+
+        let hash = match self.values.entry(key).or_insert_with(|| Value::Hash(BTreeMap::new())) {
+            Value::Hash(hash) => hash,
+            _ => return Reply::wrong_type(),
+        };
+
+        let mut added = 0;
+
+        while let (Some(field), Some(value)) = (args.next(), args.next()) {
+            if hash.insert(field, value).is_none() {
+                added += 1;
+            }
+        }
+
+        Reply::Integer(added)
+    }
+
+    fn incr(&mut self, key: &[u8]) -> Reply {
+        let value = match self.values.get(key) {
+            Some(Value::String(value)) => match parse_int(value) {
+                Some(value) => value + 1,
+                None => return Reply::not_integer(),
+            },
+            Some(_) => return Reply::wrong_type(),
+            None => 1,
+        };
+
+        self.values.insert(key.to_vec(), Value::String(value.to_string().into_bytes()));
+
+        Reply::Integer(value)
+    }
+
+    fn lindex(&self, key: &[u8], index: &[u8]) -> Reply {
+        let index = match parse_int(index) {
+            Some(index) => index,
+            None => return Reply::not_integer(),
+        };
+
+        let list = match self.values.get(key) {
+            Some(Value::List(list)) => list,
+            Some(_) => return Reply::wrong_type(),
+            None => return Reply::Bulk(None),
+        };
+
+        let index = if index < 0 { list.len() as i64 + index } else { index };
+
+        if index < 0 {
+            return Reply::Bulk(None);
+        }
+
+        Reply::Bulk(list.get(index as usize).cloned())
+    }
+
+    fn llen(&self, key: &[u8]) -> Reply {
+        match self.values.get(key) {
+            Some(Value::List(list)) => Reply::Integer(list.len() as i64),
+            Some(_) => Reply::wrong_type(),
+            None => Reply::Integer(0),
+        }
+    }
+
+    fn lrange(&self, key: &[u8], start: &[u8], stop: &[u8]) -> Reply {
+        let (start, stop) = match (parse_int(start), parse_int(stop)) {
+            (Some(start), Some(stop)) => (start, stop),
+            _ => return Reply::not_integer(),
+        };
+
+        let list = match self.values.get(key) {
+            Some(Value::List(list)) => list,
+            Some(_) => return Reply::wrong_type(),
+            None => return Reply::Array(vec![]),
+        };
+
+        match index_range(list.len(), start, stop) {
+            Some((start, len)) => Reply::bulks(list.iter().skip(start).take(len).cloned()),
+            None => Reply::Array(vec![]),
+        }
+    }
+
+    fn lrem(&mut self, args: Vec<Vec<u8>>) -> Reply {
+        let mut args = args.into_iter();
+        let key = args.next().unwrap();
+        let count = match args.next().and_then(|count| parse_int(&count)) {
+            Some(count) => count,
+            None => return Reply::not_integer(),
+        };
+        let value = args.next().unwrap();
+
+        let list = match self.values.get_mut(&key) {
+            Some(Value::List(list)) => list,
+            Some(_) => return Reply::wrong_type(),
+            None => return Reply::Integer(0),
+        };
+
+        let limit = if count == 0 { list.len() } else { count.abs() as usize };
+        let mut positions = list
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| **entry == value)
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+
+        // A negative count removes from the tail.
+        if count < 0 {
+            positions.reverse();
+        }
+
+        positions.truncate(limit);
+        positions.sort();
+
+        for idx in positions.iter().rev() {
+            list.remove(*idx);
+        }
+
+        self.remove_if_empty(&key);
+
+        Reply::Integer(positions.len() as i64)
+    }
+
+    fn lset(&mut self, args: Vec<Vec<u8>>) -> Reply {
+        let mut args = args.into_iter();
+        let key = args.next().unwrap();
+        let index = match args.next().and_then(|index| parse_int(&index)) {
+            Some(index) => index,
+            None => return Reply::not_integer(),
+        };
+        let value = args.next().unwrap();
+
+        let list = match self.values.get_mut(&key) {
+            Some(Value::List(list)) => list,
+            Some(_) => return Reply::wrong_type(),
+            None => return Reply::Error("ERR no such key".to_owned()),
+        };
+
+        let len = list.len() as i64;
+        let index = if index < 0 { len + index } else { index };
+
+        match list.get_mut(index as usize) {
+            Some(entry) if index >= 0 => *entry = value,
+            _ => return Reply::Error("ERR index out of range".to_owned()),
+        }
+
+        Reply::Simple("OK")
+    }
+
+    fn ltrim(&mut self, key: &[u8], start: &[u8], stop: &[u8]) -> Reply {
+        let (start, stop) = match (parse_int(start), parse_int(stop)) {
+            (Some(start), Some(stop)) => (start, stop),
+            _ => return Reply::not_integer(),
+        };
+
+        let list = match self.values.get_mut(key) {
+            Some(Value::List(list)) => list,
+            Some(_) => return Reply::wrong_type(),
+            None => return Reply::Simple("OK"),
+        };
+
+        match index_range(list.len(), start, stop) {
+            Some((start, len)) => {
+                list.truncate(start + len);
+                list.drain(..start);
+            },
+            None => list.clear(),
+        }
+
+        self.remove_if_empty(key);
+
+        Reply::Simple("OK")
+    }
+
+    fn mget(&self, keys: Vec<Vec<u8>>) -> Reply {
+        Reply::Array(keys.iter().map(|key| match self.values.get(key) {
+            Some(Value::String(value)) => Reply::Bulk(Some(value.clone())),
+            _ => Reply::Bulk(None),
+        }).collect())
+    }
+
+    fn pttl(&self, key: &[u8]) -> Reply {
+        if !self.values.contains_key(key) {
+            return Reply::Integer(-2);
+        }
+
+        match self.expiries.get(key) {
+            Some(expiry) => {
+                let now = Instant::now();
+                let ttl = if *expiry > now { *expiry - now } else { Duration::from_secs(0) };
+
+                Reply::Integer((ttl.as_secs() * 1000 + u64::from(ttl.subsec_millis())) as i64)
+            },
+            None => Reply::Integer(-1),
+        }
+    }
+
+    fn publish(&mut self, args: Vec<Vec<u8>>) -> Reply {
+        let mut args = args.into_iter();
+        let channel = args.next().unwrap();
+        let message = args.next().unwrap();
+
+        self.published.entry(channel).or_insert_with(Vec::new).push(message);
+
+        Reply::Integer(0)
+    }
+
+    fn push(&mut self, args: Vec<Vec<u8>>, left: bool) -> Reply {
+        let mut args = args.into_iter();
+        let key = args.next().unwrap();
+
+        let list = match self.values.entry(key).or_insert_with(|| Value::List(VecDeque::new())) {
+            Value::List(list) => list,
+            _ => return Reply::wrong_type(),
+        };
+
+        for value in args {
+            if left {
+                list.push_front(value);
+            } else {
+                list.push_back(value);
+            }
+        }
+
+        Reply::Integer(list.len() as i64)
+    }
+
+    fn sadd(&mut self, args: Vec<Vec<u8>>) -> Reply {
+        let mut args = args.into_iter();
+        let key = args.next().unwrap();
+
+        let set = match self.values.entry(key).or_insert_with(|| Value::Set(BTreeSet::new())) {
+            Value::Set(set) => set,
+            _ => return Reply::wrong_type(),
+        };
+
+        Reply::Integer(args.filter(|member| set.insert(member.clone())).count() as i64)
+    }
+
+    /// Returns every key matching the pattern in one batch, ignoring the
+    /// cursor and count.
+    fn scan(&self, args: Vec<Vec<u8>>) -> Reply {
+        let mut pattern = &b"*"[..];
+
+        for option in args[1..].chunks(2) {
+            match option {
+                [name, value] if name.eq_ignore_ascii_case(b"MATCH") => pattern = &value[..],
+                [name, _] if name.eq_ignore_ascii_case(b"COUNT") => {},
+                _ => return Reply::Error("ERR syntax error".to_owned()),
+            }
+        }
+
+        let mut keys = self.values
+            .keys()
+            .filter(|key| glob_match(pattern, key))
+            .cloned()
+            .collect::<Vec<_>>();
+        keys.sort();
+
+        Reply::scan(keys)
+    }
+
+    fn scard(&self, key: &[u8]) -> Reply {
+        match self.values.get(key) {
+            Some(Value::Set(set)) => Reply::Integer(set.len() as i64),
+            Some(_) => Reply::wrong_type(),
+            None => Reply::Integer(0),
+        }
+    }
+
+    fn set(&mut self, args: Vec<Vec<u8>>) -> Reply {
+        let mut args = args.into_iter();
+        let key = args.next().unwrap();
+        let value = args.next().unwrap();
+
+        self.expiries.remove(&key);
+        self.values.insert(key, Value::String(value));
+
+        Reply::Simple("OK")
+    }
+
+    fn sinter(&self, keys: Vec<Vec<u8>>) -> Reply {
+        let mut sets = Vec::with_capacity(keys.len());
+
+        for key in &keys {
+            match self.values.get(key) {
+                Some(Value::Set(set)) => sets.push(set),
+                Some(_) => return Reply::wrong_type(),
+                None => return Reply::Array(vec![]),
+            }
+        }
+
+        let (first, rest) = sets.split_first().unwrap();
+
+        Reply::bulks(first.iter().filter(|member| {
+            rest.iter().all(|set| set.contains(*member))
+        }).cloned())
+    }
+
+    fn smembers(&self, key: &[u8]) -> Reply {
+        match self.values.get(key) {
+            Some(Value::Set(set)) => Reply::bulks(set.iter().cloned()),
+            Some(_) => Reply::wrong_type(),
+            None => Reply::Array(vec![]),
+        }
+    }
+
+    /// Returns every member in one batch, ignoring the cursor and count.
+    fn sscan(&self, key: &[u8]) -> Reply {
+        match self.values.get(key) {
+            Some(Value::Set(set)) => Reply::scan(set.iter().cloned()),
+            Some(_) => Reply::wrong_type(),
+            None => Reply::scan(vec![]),
+        }
+    }
+
+    fn srem(&mut self, args: Vec<Vec<u8>>) -> Reply {
+        let mut args = args.into_iter();
+        let key = args.next().unwrap();
+
+        let count = match self.values.get_mut(&key) {
+            Some(Value::Set(set)) => args.filter(|member| set.remove(member)).count(),
+            Some(_) => return Reply::wrong_type(),
+            None => 0,
+        };
+
+        self.remove_if_empty(&key);
+
+        Reply::Integer(count as i64)
+    }
+
+    fn time(&self) -> Reply {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        Reply::bulks(vec![
+            now.as_secs().to_string().into_bytes(),
+            now.subsec_micros().to_string().into_bytes(),
+        ])
+    }
+
+    fn zadd(&mut self, args: Vec<Vec<u8>>) -> Reply {
+        let mut args = args.into_iter();
+        let key = args.next().unwrap();
+        let mut members = Vec::new();
+
+        while let (Some(score), Some(member)) = (args.next(), args.next()) {
+            match parse_score(&score) {
+                Some(score) => members.push((member, score)),
+                None => return Reply::Error("ERR value is not a valid float".to_owned()),
+            }
+        }
+
+        let set = match self.values.entry(key).or_insert_with(|| Value::SortedSet(BTreeMap::new())) {
+            Value::SortedSet(set) => set,
+            _ => return Reply::wrong_type(),
+        };
+
+        let added = members
+            .into_iter()
+            .filter(|(member, score)| set.insert(member.clone(), *score).is_none())
+            .count();
+
+        Reply::Integer(added as i64)
+    }
+
+    fn zcard(&self, key: &[u8]) -> Reply {
+        match self.values.get(key) {
+            Some(Value::SortedSet(set)) => Reply::Integer(set.len() as i64),
+            Some(_) => Reply::wrong_type(),
+            None => Reply::Integer(0),
+        }
+    }
+
+    fn zrange(&self, args: Vec<Vec<u8>>) -> Reply {
+        let (start, stop) = match (parse_int(&args[1]), parse_int(&args[2])) {
+            (Some(start), Some(stop)) => (start, stop),
+            _ => return Reply::not_integer(),
+        };
+        let with_scores = match args.get(3) {
+            Some(option) if option.eq_ignore_ascii_case(b"WITHSCORES") => true,
+            Some(_) => return Reply::Error("ERR syntax error".to_owned()),
+            None => false,
+        };
+
+        let members = match self.values.get(&args[0]) {
+            Some(Value::SortedSet(set)) => by_score(set),
+            Some(_) => return Reply::wrong_type(),
+            None => return Reply::Array(vec![]),
+        };
+
+        let (start, len) = match index_range(members.len(), start, stop) {
+            Some(range) => range,
+            None => return Reply::Array(vec![]),
+        };
+
+        Reply::bulks(members.into_iter().skip(start).take(len).flat_map(|(member, score)| {
+            let mut values = vec![member.clone()];
+
+            if with_scores {
+                values.push(score.to_string().into_bytes());
+            }
+
+            values
+        }))
+    }
+
+    fn zrangebylex(&self, args: Vec<Vec<u8>>) -> Reply {
+        let (min, max) = match (LexBound::parse(&args[1]), LexBound::parse(&args[2])) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return Reply::Error("ERR min or max not valid string range item".to_owned()),
+        };
+        let (offset, count) = match &args[3..] {
+            [] => (0, None),
+            [option, offset, count] if option.eq_ignore_ascii_case(b"LIMIT") => {
+                match (parse_int(offset), parse_int(count)) {
+                    (Some(offset), Some(count)) => (offset.max(0) as usize, Some(count)),
+                    _ => return Reply::not_integer(),
+                }
+            },
+            _ => return Reply::Error("ERR syntax error".to_owned()),
+        };
+
+        let set = match self.values.get(&args[0]) {
+            Some(Value::SortedSet(set)) => set,
+            Some(_) => return Reply::wrong_type(),
+            None => return Reply::Array(vec![]),
+        };
+
+        // Members all have the same score in a lexicographical index, so they
+        // can be taken in key order. A negative count returns everything.
+        let members = set
+            .keys()
+            .filter(|member| min.is_below(member) && max.is_above(member))
+            .skip(offset)
+            .cloned();
+
+        match count {
+            Some(count) if count >= 0 => Reply::bulks(members.take(count as usize)),
+            _ => Reply::bulks(members),
+        }
+    }
+
+    fn zrem(&mut self, args: Vec<Vec<u8>>) -> Reply {
+        let mut args = args.into_iter();
+        let key = args.next().unwrap();
+
+        let count = match self.values.get_mut(&key) {
+            Some(Value::SortedSet(set)) => args.filter(|member| set.remove(member).is_some()).count(),
+            Some(_) => return Reply::wrong_type(),
+            None => 0,
+        };
+
+        self.remove_if_empty(&key);
+
+        Reply::Integer(count as i64)
+    }
+
+    fn zremrangebyscore(&mut self, key: &[u8], min: &[u8], max: &[u8]) -> Reply {
+        let (min, max) = match (ScoreBound::parse(min), ScoreBound::parse(max)) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return Reply::Error("ERR min or max is not a float".to_owned()),
+        };
+
+        let count = match self.values.get_mut(key) {
+            Some(Value::SortedSet(set)) => {
+                let removed = set
+                    .iter()
+                    .filter(|(_, score)| min.is_below(**score) && max.is_above(**score))
+                    .map(|(member, _)| member.clone())
+                    .collect::<Vec<_>>();
+
+                for member in &removed {
+                    set.remove(member);
+                }
+
+                removed.len()
+            },
+            Some(_) => return Reply::wrong_type(),
+            None => 0,
+        };
+
+        self.remove_if_empty(key);
+
+        Reply::Integer(count as i64)
+    }
+
+    /// Records that a command was received, returning how long to delay its
+    /// reply by.
+    fn receive(&mut self, args: &[Vec<u8>]) -> Option<Duration> {
+        let command = String::from_utf8_lossy(args.first()?).to_uppercase();
+        *self.received.entry(command.clone()).or_insert(0) += 1;
+
+        let (remaining, delay) = self.delays.get_mut(&command)?;
+
+        if *remaining == 0 {
+            return None;
+        }
+
+        *remaining -= 1;
+
+        Some(*delay)
+    }
+
+    /// Removes a key and its TTL, returning whether it existed.
+    fn remove(&mut self, key: &[u8]) -> bool {
+        self.expiries.remove(key);
+
+        self.values.remove(key).is_some()
+    }
+
+    /// Removes the keys whose TTLs have passed.
+    fn remove_expired(&mut self) {
+        let now = Instant::now();
+        let expired = self.expiries
+            .iter()
+            .filter(|(_, expiry)| **expiry <= now)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        for key in expired {
+            self.remove(&key);
+        }
+    }
+
+    /// Removes a key if it holds an empty collection, as Redis does.
+    fn remove_if_empty(&mut self, key: &[u8]) {
+        let empty = match self.values.get(key) {
+            Some(Value::Hash(hash)) => hash.is_empty(),
+            Some(Value::List(list)) => list.is_empty(),
+            Some(Value::Set(set)) => set.is_empty(),
+            Some(Value::SortedSet(set)) => set.is_empty(),
+            _ => false,
+        };
+
+        if empty {
+            self.remove(key);
+        }
+    }
+}
+
+/// A bound of a ZRANGEBYLEX range.
+enum LexBound {
+    Inclusive(Vec<u8>),
+    Exclusive(Vec<u8>),
+    /// `-`, below every member.
+    Min,
+    /// `+`, above every member.
+    Max,
+}
+
+impl LexBound {
+    fn parse(bound: &[u8]) -> Option<Self> {
+        match bound.split_first()? {
+            (b'[', value) => Some(LexBound::Inclusive(value.to_vec())),
+            (b'(', value) => Some(LexBound::Exclusive(value.to_vec())),
+            (b'-', []) => Some(LexBound::Min),
+            (b'+', []) => Some(LexBound::Max),
+            _ => None,
+        }
+    }
+
+    /// Whether the bound allows a member as the start of a range.
+    fn is_below(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Inclusive(value) => member >= &value[..],
+            LexBound::Exclusive(value) => member > &value[..],
+            LexBound::Min => true,
+            LexBound::Max => false,
+        }
+    }
+
+    /// Whether the bound allows a member as the end of a range.
+    fn is_above(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Inclusive(value) => member <= &value[..],
+            LexBound::Exclusive(value) => member < &value[..],
+            LexBound::Min => false,
+            LexBound::Max => true,
+        }
+    }
+}
+
+/// A bound of a score range, exclusive when prefixed with `(`.
+struct ScoreBound {
+    exclusive: bool,
+    score: f64,
+}
+
+impl ScoreBound {
+    fn parse(bound: &[u8]) -> Option<Self> {
+        match bound.split_first()? {
+            (b'(', score) => Some(ScoreBound {
+                exclusive: true,
+                score: parse_score(score)?,
+            }),
+            _ => Some(ScoreBound {
+                exclusive: false,
+                score: parse_score(bound)?,
+            }),
+        }
+    }
+
+    fn is_below(&self, score: f64) -> bool {
+        if self.exclusive { score > self.score } else { score >= self.score }
+    }
+
+    fn is_above(&self, score: f64) -> bool {
+        if self.exclusive { score < self.score } else { score <= self.score }
+    }
+}
+
+fn parse_int(bytes: &[u8]) -> Option<i64> {
+    std::str::from_utf8(bytes).ok()?.parse().ok()
+}
+
+fn parse_score(bytes: &[u8]) -> Option<f64> {
+    match std::str::from_utf8(bytes).ok()? {
+        "+inf" | "inf" => Some(std::f64::INFINITY),
+        "-inf" => Some(std::f64::NEG_INFINITY),
+        score => score.parse().ok().filter(|score: &f64| !score.is_nan()),
+    }
+}
+
+/// Resolves an inclusive index range that may count from the end, returning
+/// the start and length of the part within `0..len`.
+fn index_range(len: usize, start: i64, stop: i64) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { (len + start).max(0) } else { start };
+    let stop = if stop < 0 { len + stop } else { stop.min(len - 1) };
+
+    if start > stop {
+        return None;
+    }
+
+    Some((start as usize, (stop - start + 1) as usize))
+}
+
+/// Returns a sorted Set's members ordered by score, then by member.
+fn by_score(set: &BTreeMap<Vec<u8>, f64>) -> Vec<(&Vec<u8>, f64)> {
+    let mut members = set.iter().map(|(member, score)| (member, *score)).collect::<Vec<_>>();
+    members.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then_with(|| a.0.cmp(b.0)));
+
+    members
+}
+
+/// Matches a key against a glob-style pattern with `*` and `?` wildcards.
+fn glob_match(pattern: &[u8], key: &[u8]) -> bool {
+    match pattern.split_first() {
+        Some((b'*', rest)) => (0..=key.len()).any(|idx| glob_match(rest, &key[idx..])),
+        Some((b'?', rest)) => !key.is_empty() && glob_match(rest, &key[1..]),
+        Some((byte, rest)) => key.first() == Some(byte) && glob_match(rest, &key[1..]),
+        None => key.is_empty(),
+    }
+}
+
+/// An in-process server speaking the subset of RESP used by the cache.
+///
+/// The supported commands are DEL, EVAL, EXISTS, EXPIRE, GET, HDEL, HGET,
+/// HGETALL, HMGET, HMSET, HSCAN, HSET, INCR, LINDEX, LLEN, LPUSH, LRANGE,
+/// LREM, LSET, LTRIM, MGET, PEXPIRE, PTTL, PUBLISH, RPUSH, SADD, SCAN, SCARD,
+/// SET, SINTER, SMEMBERS, SREM, SSCAN, TIME, ZADD, ZCARD, ZRANGE, ZRANGEBYLEX,
+/// ZREM, and ZREMRANGEBYSCORE, without their optional flags. Anything else receives
+/// an error reply. Scans return everything in their first batch.
+///
+/// Scripts run in embedded Lua with `redis.call`, `cjson`, and `unpack`, and
+/// can call any of the other supported commands. Nothing can subscribe, so
+/// published messages are only recorded, for checking with [`published`].
+///
+/// Replies can be delayed with [`delay_replies`], to test timeouts and
+/// retries.
+///
+/// The server runs on background threads for the rest of the process.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # #![feature(async_await, await_macro, futures_api)]
+/// use dabbot_cache::{test_util::FakeRedis, Cache};
+/// use futures::compat::Future01CompatExt;
+/// use redis_async::client;
+/// use std::sync::Arc;
+///
+/// # async fn run() -> Result<(), Box<std::error::Error>> {
+/// let server = FakeRedis::start()?;
+/// let connection = await!(client::paired_connect(&server.addr()).compat())?;
+/// let cache = Cache::new(Arc::new(connection));
+/// # Ok(())
+/// # }
+/// # fn main() {}
+/// ```
+///
+/// [`delay_replies`]: #method.delay_replies
+/// [`published`]: #method.published
+#[derive(Clone, Debug)]
+pub struct FakeRedis {
+    addr: SocketAddr,
+    store: Arc<Mutex<Store>>,
+}
+
+impl FakeRedis {
+    /// Starts a server listening on an ephemeral port on localhost.
+    pub fn start() -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let store = Arc::new(Mutex::new(Store::default()));
+
+        let accept_store = Arc::clone(&store);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(why) => {
+                        warn!("Err accepting fake redis connection: {:?}", why);
+
+                        continue;
+                    },
+                };
+                let store = Arc::clone(&accept_store);
+
+                thread::spawn(move || {
+                    if let Err(why) = handle(stream, &store) {
+                        debug!("Fake redis connection closed: {:?}", why);
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            addr,
+            store,
+        })
+    }
+
+    /// Returns the address that the server is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the value stored at a key, if any.
+    pub fn value(&self, key: &str) -> Option<Value> {
+        let mut store = self.store.lock().unwrap();
+        store.remove_expired();
+
+        store.values.get(key.as_bytes()).cloned()
+    }
+
+    /// Returns all of the stored keys, sorted.
+    pub fn keys(&self) -> Vec<String> {
+        let mut store = self.store.lock().unwrap();
+        store.remove_expired();
+
+        let mut keys = store.values
+            .keys()
+            .map(|key| String::from_utf8_lossy(key).into_owned())
+            .collect::<Vec<_>>();
+        keys.sort();
+
+        keys
+    }
+
+    /// Delays the replies to the next `count` commands named `command` by
+    /// `delay`, as if the server had stalled.
+    ///
+    /// The commands still run immediately. Later replies on the same
+    /// connection wait behind a delayed one, as they would with Redis.
+    pub fn delay_replies(&self, command: &str, count: usize, delay: Duration) {
+        let mut store = self.store.lock().unwrap();
+
+        store.delays.insert(command.to_uppercase(), (count, delay));
+    }
+
+    /// Returns the number of times a command has been received, not counting
+    /// those called by scripts.
+    pub fn received(&self, command: &str) -> usize {
+        let store = self.store.lock().unwrap();
+
+        store.received.get(&command.to_uppercase()).cloned().unwrap_or(0)
+    }
+
+    /// Returns the messages published to a channel, oldest first.
+    pub fn published(&self, channel: &str) -> Vec<Vec<u8>> {
+        let store = self.store.lock().unwrap();
+
+        store.published.get(channel.as_bytes()).cloned().unwrap_or_default()
+    }
+
+    /// Removes all stored keys.
+    pub fn flush(&self) {
+        let mut store = self.store.lock().unwrap();
+        store.expiries.clear();
+        store.values.clear();
+    }
+}
+
+fn handle(stream: TcpStream, store: &Mutex<Store>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    loop {
+        let args = match read_command(&mut reader)? {
+            Some(args) => args,
+            None => return Ok(()),
+        };
+
+        let (reply, delay) = {
+            let mut store = store.lock().unwrap();
+            let delay = store.receive(&args);
+
+            (store.execute(args), delay)
+        };
+
+        // Sleep without the lock, so that other connections aren't stalled.
+        if let Some(delay) = delay {
+            thread::sleep(delay);
+        }
+
+        let mut buf = Vec::new();
+        reply.write_to(&mut buf);
+        writer.write_all(&buf)?;
+    }
+}
+
+/// Reads a command, sent as an array of bulk strings.
+///
+/// Returns `None` once the connection is closed.
+fn read_command<R: BufRead>(reader: &mut R) -> io::Result<Option<Vec<Vec<u8>>>> {
+    let line = match read_line(reader)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+
+    let count = parse_header(&line, b'*')?;
+    let mut args = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        let line = read_line(reader)?.ok_or_else(|| invalid("Unexpected EOF"))?;
+        let len = parse_header(&line, b'$')?;
+
+        // Read the value and its trailing CRLF.
+        let mut value = vec![0; len + 2];
+        reader.read_exact(&mut value)?;
+        value.truncate(len);
+
+        args.push(value);
+    }
+
+    Ok(Some(args))
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+
+    if !line.ends_with(b"\r\n") {
+        return Err(invalid("Line didn't end with CRLF"));
+    }
+
+    line.truncate(line.len() - 2);
+
+    Ok(Some(line))
+}
+
+fn parse_header(line: &[u8], prefix: u8) -> io::Result<usize> {
+    if line.first() != Some(&prefix) {
+        return Err(invalid("Unexpected RESP type"));
+    }
+
+    std::str::from_utf8(&line[1..])
+        .ok()
+        .and_then(|len| len.parse().ok())
+        .ok_or_else(|| invalid("Invalid length"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        thread,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+    use super::{parse_int, Reply, Store};
+
+    fn execute(store: &mut Store, args: &[&str]) -> Reply {
+        store.execute(args.iter().map(|arg| arg.as_bytes().to_vec()).collect())
+    }
+
+    fn bulk(value: &str) -> Reply {
+        Reply::Bulk(Some(value.as_bytes().to_vec()))
+    }
+
+    #[test]
+    fn test_strings() {
+        let mut store = Store::default();
+
+        assert_eq!(execute(&mut store, &["GET", "j:1"]), Reply::Bulk(None));
+        assert_eq!(execute(&mut store, &["SET", "j:1", "2"]), Reply::Simple("OK"));
+        assert_eq!(execute(&mut store, &["GET", "j:1"]), bulk("2"));
+        assert_eq!(execute(&mut store, &["INCR", "j:1"]), Reply::Integer(3));
+        assert_eq!(execute(&mut store, &["INCR", "j:2"]), Reply::Integer(1));
+        assert_eq!(execute(&mut store, &["DEL", "j:2"]), Reply::Integer(1));
+        assert_eq!(execute(&mut store, &["MGET", "j:1", "j:2"]), Reply::Array(vec![
+            bulk("3"),
+            Reply::Bulk(None),
+        ]));
+        assert_eq!(execute(&mut store, &["DEL", "j:1", "j:2"]), Reply::Integer(1));
+    }
+
+    #[test]
+    fn test_hashes() {
+        let mut store = Store::default();
+
+        execute(&mut store, &["HMSET", "g:1", "name", "a guild", "region", "us-west"]);
+        assert_eq!(execute(&mut store, &["HGET", "g:1", "name"]), bulk("a guild"));
+        assert_eq!(execute(&mut store, &["HDEL", "g:1", "region", "nope"]), Reply::Integer(1));
+        assert_eq!(execute(&mut store, &["HGETALL", "g:1"]), Reply::Array(vec![
+            bulk("name"),
+            bulk("a guild"),
+        ]));
+        assert_eq!(execute(&mut store, &["HSET", "g:1", "name", "b", "icon", "c"]), Reply::Integer(1));
+        assert_eq!(execute(&mut store, &["HMGET", "g:1", "icon", "nope"]), Reply::Array(vec![
+            bulk("c"),
+            Reply::Bulk(None),
+        ]));
+        assert_eq!(execute(&mut store, &["HSCAN", "g:1", "0", "COUNT", "10"]), Reply::Array(vec![
+            bulk("0"),
+            Reply::Array(vec![bulk("icon"), bulk("c"), bulk("name"), bulk("b")]),
+        ]));
+        assert_eq!(execute(&mut store, &["HDEL", "g:1", "name", "icon"]), Reply::Integer(2));
+        assert_eq!(execute(&mut store, &["EXISTS", "g:1"]), Reply::Integer(0));
+    }
+
+    #[test]
+    fn test_sets() {
+        let mut store = Store::default();
+
+        assert_eq!(execute(&mut store, &["SADD", "g:1:m", "2", "3", "2"]), Reply::Integer(2));
+        assert_eq!(execute(&mut store, &["SCARD", "g:1:m"]), Reply::Integer(2));
+        assert_eq!(execute(&mut store, &["SREM", "g:1:m", "2"]), Reply::Integer(1));
+        assert_eq!(execute(&mut store, &["SMEMBERS", "g:1:m"]), Reply::Array(vec![bulk("3")]));
+        assert_eq!(execute(&mut store, &["SSCAN", "g:1:m", "0", "COUNT", "10"]), Reply::Array(vec![
+            bulk("0"),
+            Reply::Array(vec![bulk("3")]),
+        ]));
+
+        execute(&mut store, &["SADD", "g:2:m", "3", "4"]);
+        assert_eq!(execute(&mut store, &["SINTER", "g:1:m", "g:2:m"]), Reply::Array(vec![bulk("3")]));
+        assert_eq!(execute(&mut store, &["SINTER", "g:1:m", "g:3:m"]), Reply::Array(vec![]));
+    }
+
+    #[test]
+    fn test_sorted_sets() {
+        let mut store = Store::default();
+
+        assert_eq!(execute(&mut store, &["ZADD", "rl:1", "20", "b", "10", "a"]), Reply::Integer(2));
+        assert_eq!(execute(&mut store, &["ZADD", "rl:1", "30", "c", "5", "b"]), Reply::Integer(1));
+        assert_eq!(execute(&mut store, &["ZCARD", "rl:1"]), Reply::Integer(3));
+        assert_eq!(execute(&mut store, &["ZRANGE", "rl:1", "0", "0", "WITHSCORES"]), Reply::Array(vec![
+            bulk("b"),
+            bulk("5"),
+        ]));
+        assert_eq!(execute(&mut store, &["ZRANGE", "rl:1", "1", "-1"]), Reply::Array(vec![
+            bulk("a"),
+            bulk("c"),
+        ]));
+        assert_eq!(execute(&mut store, &["ZREMRANGEBYSCORE", "rl:1", "-inf", "(10"]), Reply::Integer(1));
+        assert_eq!(execute(&mut store, &["ZREM", "rl:1", "a", "nope"]), Reply::Integer(1));
+        assert_eq!(execute(&mut store, &["ZREMRANGEBYSCORE", "rl:1", "30", "+inf"]), Reply::Integer(1));
+        assert_eq!(execute(&mut store, &["EXISTS", "rl:1"]), Reply::Integer(0));
+    }
+
+    #[test]
+    fn test_zrangebylex() {
+        let mut store = Store::default();
+
+        execute(&mut store, &["ZADD", "g:1:ms", "0", "dab", "0", "dabbot", "0", "zeyla", "0", "da"]);
+        assert_eq!(execute(&mut store, &["ZRANGEBYLEX", "g:1:ms", "[dab", "[dab~"]), Reply::Array(vec![
+            bulk("dab"),
+            bulk("dabbot"),
+        ]));
+        assert_eq!(execute(&mut store, &["ZRANGEBYLEX", "g:1:ms", "(dab", "+"]), Reply::Array(vec![
+            bulk("dabbot"),
+            bulk("zeyla"),
+        ]));
+        assert_eq!(execute(&mut store, &["ZRANGEBYLEX", "g:1:ms", "-", "+", "LIMIT", "1", "2"]), Reply::Array(vec![
+            bulk("dab"),
+            bulk("dabbot"),
+        ]));
+
+        match execute(&mut store, &["ZRANGEBYLEX", "g:1:ms", "dab", "+"]) {
+            Reply::Error(why) => assert!(why.starts_with("ERR")),
+            other => panic!("Expected an error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_expiry() {
+        let mut store = Store::default();
+
+        execute(&mut store, &["SET", "j:1", "2"]);
+        assert_eq!(execute(&mut store, &["PTTL", "j:1"]), Reply::Integer(-1));
+        assert_eq!(execute(&mut store, &["PTTL", "j:2"]), Reply::Integer(-2));
+        assert_eq!(execute(&mut store, &["EXPIRE", "j:2", "10"]), Reply::Integer(0));
+        assert_eq!(execute(&mut store, &["EXPIRE", "j:1", "10"]), Reply::Integer(1));
+
+        match execute(&mut store, &["PTTL", "j:1"]) {
+            Reply::Integer(ttl) => assert!(ttl > 9000 && ttl <= 10000),
+            other => panic!("Expected a TTL: {:?}", other),
+        }
+
+        // Setting a value clears its TTL.
+        execute(&mut store, &["SET", "j:1", "3"]);
+        assert_eq!(execute(&mut store, &["PTTL", "j:1"]), Reply::Integer(-1));
+
+        assert_eq!(execute(&mut store, &["PEXPIRE", "j:1", "1"]), Reply::Integer(1));
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(execute(&mut store, &["GET", "j:1"]), Reply::Bulk(None));
+        assert!(store.expiries.is_empty());
+    }
+
+    #[test]
+    fn test_scan() {
+        let mut store = Store::default();
+
+        execute(&mut store, &["SET", "g:1", "a"]);
+        execute(&mut store, &["SET", "g:1:m", "b"]);
+        execute(&mut store, &["SET", "g:23", "c"]);
+        execute(&mut store, &["SET", "u:1", "d"]);
+
+        assert_eq!(execute(&mut store, &["SCAN", "0", "MATCH", "g:?", "COUNT", "10"]), Reply::Array(vec![
+            bulk("0"),
+            Reply::Array(vec![bulk("g:1")]),
+        ]));
+        assert_eq!(execute(&mut store, &["SCAN", "0", "MATCH", "g:*"]), Reply::Array(vec![
+            bulk("0"),
+            Reply::Array(vec![bulk("g:1"), bulk("g:1:m"), bulk("g:23")]),
+        ]));
+    }
+
+    #[test]
+    fn test_time() {
+        let mut store = Store::default();
+        let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+
+        match execute(&mut store, &["TIME"]) {
+            Reply::Array(ref replies) if replies.len() == 2 => match (&replies[0], &replies[1]) {
+                (Reply::Bulk(Some(secs)), Reply::Bulk(Some(micros))) => {
+                    assert!(parse_int(secs).unwrap() >= before as i64);
+                    assert!(parse_int(micros).unwrap() < 1_000_000);
+                },
+                other => panic!("Expected bulk strings: {:?}", other),
+            },
+            other => panic!("Expected the time: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_lists() {
+        let mut store = Store::default();
+
+        execute(&mut store, &["RPUSH", "queue:1", "b", "c"]);
+        execute(&mut store, &["LPUSH", "queue:1", "a"]);
+        assert_eq!(execute(&mut store, &["LLEN", "queue:1"]), Reply::Integer(3));
+        assert_eq!(execute(&mut store, &["LRANGE", "queue:1", "0", "-1"]), Reply::Array(vec![
+            bulk("a"),
+            bulk("b"),
+            bulk("c"),
+        ]));
+        assert_eq!(execute(&mut store, &["LRANGE", "queue:1", "1", "1"]), Reply::Array(vec![
+            bulk("b"),
+        ]));
+        assert_eq!(execute(&mut store, &["LRANGE", "queue:1", "5", "10"]), Reply::Array(vec![]));
+        assert_eq!(execute(&mut store, &["LSET", "queue:1", "-1", "d"]), Reply::Simple("OK"));
+        assert_eq!(execute(&mut store, &["LRANGE", "queue:1", "2", "2"]), Reply::Array(vec![
+            bulk("d"),
+        ]));
+
+        match execute(&mut store, &["LSET", "queue:1", "3", "e"]) {
+            Reply::Error(why) => assert!(why.starts_with("ERR")),
+            other => panic!("Expected an error: {:?}", other),
+        }
+
+        assert_eq!(execute(&mut store, &["LINDEX", "queue:1", "-1"]), bulk("d"));
+        assert_eq!(execute(&mut store, &["LINDEX", "queue:1", "3"]), Reply::Bulk(None));
+        assert_eq!(execute(&mut store, &["LTRIM", "queue:1", "1", "-1"]), Reply::Simple("OK"));
+        assert_eq!(execute(&mut store, &["LRANGE", "queue:1", "0", "-1"]), Reply::Array(vec![
+            bulk("b"),
+            bulk("d"),
+        ]));
+
+        execute(&mut store, &["RPUSH", "queue:2", "a", "b", "a", "c", "a"]);
+        assert_eq!(execute(&mut store, &["LREM", "queue:2", "-2", "a"]), Reply::Integer(2));
+        assert_eq!(execute(&mut store, &["LRANGE", "queue:2", "0", "-1"]), Reply::Array(vec![
+            bulk("a"),
+            bulk("b"),
+            bulk("c"),
+        ]));
+        assert_eq!(execute(&mut store, &["LREM", "queue:2", "0", "b"]), Reply::Integer(1));
+        assert_eq!(execute(&mut store, &["LTRIM", "queue:2", "5", "10"]), Reply::Simple("OK"));
+        assert_eq!(execute(&mut store, &["EXISTS", "queue:2"]), Reply::Integer(0));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut store = Store::default();
+
+        execute(&mut store, &["SET", "j:1", "2"]);
+
+        match execute(&mut store, &["SADD", "j:1", "2"]) {
+            Reply::Error(why) => assert!(why.starts_with("WRONGTYPE")),
+            other => panic!("Expected an error: {:?}", other),
+        }
+
+        match execute(&mut store, &["ZADD", "j:1", "0", "2"]) {
+            Reply::Error(why) => assert!(why.starts_with("WRONGTYPE")),
+            other => panic!("Expected an error: {:?}", other),
+        }
+
+        match execute(&mut store, &["BLPOP", "queue:1", "0"]) {
+            Reply::Error(why) => assert!(why.starts_with("ERR")),
+            other => panic!("Expected an error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_receive() {
+        let mut store = Store::default();
+        store.delays.insert("LRANGE".to_owned(), (1, Duration::from_millis(5)));
+
+        let lrange = vec![b"lrange".to_vec(), b"queue:1".to_vec(), b"0".to_vec(), b"-1".to_vec()];
+        assert_eq!(store.receive(&lrange), Some(Duration::from_millis(5)));
+        assert_eq!(store.receive(&lrange), None);
+        assert_eq!(store.receive(&[b"LLEN".to_vec(), b"queue:1".to_vec()]), None);
+        assert_eq!(store.received["LRANGE"], 2);
+        assert_eq!(store.received["LLEN"], 1);
+    }
+
+    #[test]
+    fn test_read_command() {
+        let mut input = &b"*2\r\n$3\r\nGET\r\n$3\r\nj:1\r\n"[..];
+
+        assert_eq!(super::read_command(&mut input).unwrap(), Some(vec![
+            b"GET".to_vec(),
+            b"j:1".to_vec(),
+        ]));
+        assert_eq!(super::read_command(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn test_write_reply() {
+        let mut buf = Vec::new();
+        Reply::Array(vec![bulk("a"), Reply::Bulk(None), Reply::Integer(2)]).write_to(&mut buf);
+
+        assert_eq!(buf, b"*3\r\n$1\r\na\r\n$-1\r\n:2\r\n".to_vec());
+    }
+}
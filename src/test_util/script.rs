@@ -0,0 +1,315 @@
+//! EVAL support for [`FakeRedis`], running scripts in embedded Lua.
+//!
+//! Only the parts of Redis' scripting environment used by the cache's scripts
+//! are provided: `KEYS`, `ARGV`, `redis.call`, `redis.replicate_commands`,
+//! `cjson`, and `unpack`. Replies are converted to and from Lua values as Redis
+//! does.
+//!
+//! [`FakeRedis`]: ../struct.FakeRedis.html
+
+use rlua::{
+    Error as LuaError,
+    Function,
+    Lua,
+    Result as LuaResult,
+    Table,
+    Value as LuaValue,
+    Variadic,
+};
+use serde_json::{Map, Number, Value as JsonValue};
+use super::{Reply, Store};
+
+/// Runs a script against the store, with the given keys and arguments.
+pub(super) fn eval(
+    store: &mut Store,
+    script: &[u8],
+    keys: Vec<Vec<u8>>,
+    argv: Vec<Vec<u8>>,
+) -> Reply {
+    let lua = Lua::new();
+
+    let result = lua.scope(|scope| -> LuaResult<Reply> {
+        let globals = lua.globals();
+        globals.set("KEYS", strings(&lua, keys)?)?;
+        globals.set("ARGV", strings(&lua, argv)?)?;
+
+        // Redis runs Lua 5.1, where `unpack` is a global.
+        let table: Table = globals.get("table")?;
+        globals.set("unpack", table.get::<_, Function>("unpack")?)?;
+
+        let redis = lua.create_table()?;
+        redis.set("call", scope.create_function_mut(|lua, args: Variadic<LuaValue>| {
+            let args = args.into_iter().map(to_arg).collect::<LuaResult<Vec<_>>>()?;
+
+            match store.execute(args) {
+                Reply::Error(why) => Err(LuaError::RuntimeError(why)),
+                reply => to_lua(lua, reply),
+            }
+        })?)?;
+        // Nothing is replicated, so scripts can always write after calling
+        // non-deterministic commands like TIME.
+        redis.set("replicate_commands", lua.create_function(|_, ()| Ok(true))?)?;
+        globals.set("redis", redis)?;
+
+        let cjson = lua.create_table()?;
+        cjson.set("encode", lua.create_function(|_, value: LuaValue| {
+            Ok(to_json(value)?.to_string())
+        })?)?;
+        cjson.set("decode", lua.create_function(|lua, json: rlua::String| {
+            let value = serde_json::from_slice(json.as_bytes())
+                .map_err(|why| LuaError::RuntimeError(why.to_string()))?;
+
+            from_json(lua, value)
+        })?)?;
+        globals.set("cjson", cjson)?;
+
+        let script = String::from_utf8_lossy(script);
+
+        from_lua(lua.exec::<_, LuaValue>(&*script, Some("@user_script"))?)
+    });
+
+    match result {
+        Ok(reply) => reply,
+        Err(why) => Reply::Error(format!("ERR Error running script: {}", why)),
+    }
+}
+
+fn strings(lua: &Lua, values: Vec<Vec<u8>>) -> LuaResult<Table> {
+    let table = lua.create_table()?;
+
+    for (idx, value) in values.into_iter().enumerate() {
+        table.set(idx + 1, lua.create_string(&value)?)?;
+    }
+
+    Ok(table)
+}
+
+/// Converts an argument to `redis.call` into a command argument.
+fn to_arg(value: LuaValue) -> LuaResult<Vec<u8>> {
+    match value {
+        LuaValue::String(string) => Ok(string.as_bytes().to_vec()),
+        LuaValue::Integer(int) => Ok(int.to_string().into_bytes()),
+        LuaValue::Number(number) => Ok(number.to_string().into_bytes()),
+        _ => Err(LuaError::RuntimeError(
+            "Lua redis() command arguments must be strings or integers".to_owned(),
+        )),
+    }
+}
+
+/// Converts a command's reply into a Lua value, with nil replies as `false`.
+fn to_lua(lua: &Lua, reply: Reply) -> LuaResult<LuaValue> {
+    Ok(match reply {
+        Reply::Array(replies) => {
+            let table = lua.create_table()?;
+
+            for (idx, reply) in replies.into_iter().enumerate() {
+                table.set(idx + 1, to_lua(lua, reply)?)?;
+            }
+
+            LuaValue::Table(table)
+        },
+        Reply::Bulk(Some(bytes)) => LuaValue::String(lua.create_string(&bytes)?),
+        Reply::Bulk(None) => LuaValue::Boolean(false),
+        Reply::Error(why) => {
+            let table = lua.create_table()?;
+            table.set("err", why)?;
+
+            LuaValue::Table(table)
+        },
+        Reply::Integer(int) => LuaValue::Integer(int),
+        Reply::Simple(status) => {
+            let table = lua.create_table()?;
+            table.set("ok", status)?;
+
+            LuaValue::Table(table)
+        },
+    })
+}
+
+/// Converts a script's return value into a reply, with `false` as nil and
+/// tables as arrays up to their first nil.
+fn from_lua(value: LuaValue) -> LuaResult<Reply> {
+    Ok(match value {
+        LuaValue::Boolean(true) => Reply::Integer(1),
+        LuaValue::Integer(int) => Reply::Integer(int),
+        LuaValue::Number(number) => Reply::Integer(number as i64),
+        LuaValue::String(string) => Reply::Bulk(Some(string.as_bytes().to_vec())),
+        LuaValue::Table(table) => {
+            if let LuaValue::String(why) = table.raw_get::<_, LuaValue>("err")? {
+                return Ok(Reply::Error(why.to_str()?.to_owned()));
+            }
+
+            if let LuaValue::String(status) = table.raw_get::<_, LuaValue>("ok")? {
+                return Ok(match status.to_str()? {
+                    "OK" => Reply::Simple("OK"),
+                    other => Reply::Bulk(Some(other.as_bytes().to_vec())),
+                });
+            }
+
+            let mut replies = Vec::new();
+
+            for value in table.sequence_values::<LuaValue>() {
+                replies.push(from_lua(value?)?);
+            }
+
+            Reply::Array(replies)
+        },
+        _ => Reply::Bulk(None),
+    })
+}
+
+/// Encodes a Lua value as JSON for `cjson.encode`, with tables as arrays if
+/// they have a sequence and as objects otherwise.
+fn to_json(value: LuaValue) -> LuaResult<JsonValue> {
+    Ok(match value {
+        LuaValue::Nil => JsonValue::Null,
+        LuaValue::Boolean(boolean) => JsonValue::Bool(boolean),
+        LuaValue::Integer(int) => JsonValue::from(int),
+        LuaValue::Number(number) => Number::from_f64(number)
+            .map(JsonValue::Number)
+            .ok_or_else(|| LuaError::RuntimeError("Cannot encode NaN or inf".to_owned()))?,
+        LuaValue::String(string) => JsonValue::String(string.to_str()?.to_owned()),
+        LuaValue::Table(table) => {
+            let len = table.len()?;
+
+            if len > 0 {
+                let mut values = Vec::with_capacity(len as usize);
+
+                for idx in 1..=len {
+                    values.push(to_json(table.raw_get(idx)?)?);
+                }
+
+                JsonValue::Array(values)
+            } else {
+                let mut map = Map::new();
+
+                for pair in table.pairs::<LuaValue, LuaValue>() {
+                    let (key, value) = pair?;
+                    let key = match key {
+                        LuaValue::String(key) => key.to_str()?.to_owned(),
+                        LuaValue::Integer(key) => key.to_string(),
+                        _ => return Err(LuaError::RuntimeError(
+                            "Cannot encode a table with non-string keys".to_owned(),
+                        )),
+                    };
+
+                    map.insert(key, to_json(value)?);
+                }
+
+                JsonValue::Object(map)
+            }
+        },
+        _ => return Err(LuaError::RuntimeError("Cannot encode value".to_owned())),
+    })
+}
+
+/// Decodes JSON into a Lua value for `cjson.decode`.
+fn from_json(lua: &Lua, value: JsonValue) -> LuaResult<LuaValue> {
+    Ok(match value {
+        JsonValue::Null => LuaValue::Nil,
+        JsonValue::Bool(boolean) => LuaValue::Boolean(boolean),
+        JsonValue::Number(number) => match number.as_i64() {
+            Some(int) => LuaValue::Integer(int),
+            None => LuaValue::Number(number.as_f64().unwrap_or_default()),
+        },
+        JsonValue::String(string) => LuaValue::String(lua.create_string(&string)?),
+        JsonValue::Array(values) => {
+            let table = lua.create_table()?;
+
+            for (idx, value) in values.into_iter().enumerate() {
+                table.set(idx + 1, from_json(lua, value)?)?;
+            }
+
+            LuaValue::Table(table)
+        },
+        JsonValue::Object(map) => {
+            let table = lua.create_table()?;
+
+            for (key, value) in map {
+                table.set(key, from_json(lua, value)?)?;
+            }
+
+            LuaValue::Table(table)
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Reply, Store};
+
+    fn eval(store: &mut Store, script: &str, keys: &[&str], argv: &[&str]) -> Reply {
+        let mut args = vec![
+            b"EVAL".to_vec(),
+            script.as_bytes().to_vec(),
+            keys.len().to_string().into_bytes(),
+        ];
+        args.extend(keys.iter().chain(argv).map(|arg| arg.as_bytes().to_vec()));
+
+        store.execute(args)
+    }
+
+    fn bulk(value: &str) -> Reply {
+        Reply::Bulk(Some(value.as_bytes().to_vec()))
+    }
+
+    #[test]
+    fn test_keys_and_argv() {
+        let mut store = Store::default();
+        let reply = eval(&mut store, "return {KEYS[1], ARGV[2], #ARGV}", &["a"], &["b", "c"]);
+
+        assert_eq!(reply, Reply::Array(vec![bulk("a"), bulk("c"), Reply::Integer(2)]));
+    }
+
+    #[test]
+    fn test_call() {
+        let mut store = Store::default();
+        let script = r#"
+            redis.call('HMSET', KEYS[1], 'a', 1, unpack(ARGV))
+            return {redis.call('HGET', KEYS[1], 'b'), redis.call('HGET', KEYS[1], 'c')}
+        "#;
+
+        // The nil reply converts to false, which converts back to nil.
+        assert_eq!(eval(&mut store, script, &["h"], &["b", "2"]), Reply::Array(vec![
+            bulk("2"),
+            Reply::Bulk(None),
+        ]));
+        assert_eq!(eval(&mut store, "return redis.call('HGET', KEYS[1], 'a')", &["h"], &[]), bulk("1"));
+    }
+
+    #[test]
+    fn test_time() {
+        let mut store = Store::default();
+        let script = r#"
+            redis.replicate_commands()
+            local time = redis.call('TIME')
+            redis.call('SET', KEYS[1], time[1])
+            return tonumber(time[2]) < 1000000
+        "#;
+
+        assert_eq!(eval(&mut store, script, &["t"], &[]), Reply::Integer(1));
+    }
+
+    #[test]
+    fn test_cjson() {
+        let mut store = Store::default();
+        let script = r#"
+            local value = cjson.decode(ARGV[1])
+            value.n = value.n + 1
+            return cjson.encode(value)
+        "#;
+
+        assert_eq!(eval(&mut store, script, &[], &[r#"{"n":1}"#]), bulk(r#"{"n":2}"#));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut store = Store::default();
+        store.execute(vec![b"SET".to_vec(), b"j:1".to_vec(), b"2".to_vec()]);
+
+        match eval(&mut store, "return redis.call('SADD', KEYS[1], 1)", &["j:1"], &[]) {
+            Reply::Error(why) => assert!(why.contains("WRONGTYPE")),
+            other => panic!("Expected an error: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{
+    cache::Cache,
+    error::{Error, Result},
+    gen,
+    model::VoiceConnectionInfo,
+};
+use futures::{
+    compat::{Future01CompatExt, Stream01CompatExt},
+    stream::{Stream, StreamExt},
+};
+use redis_async::{
+    client::PubsubConnection,
+    resp::RespValue,
+};
+
+/// Publishes a voice state's connection info if the channel ID, session ID,
+/// token, and endpoint are all present.
+///
+/// `KEYS[1]` is the voice state. `ARGV[1]` and `ARGV[2]` are the guild and
+/// user IDs, which are inserted into the JSON as is so that they keep their
+/// full precision, and `ARGV[3]` is the channel to publish to.
+const PUBLISH_IF_READY_SCRIPT: &str = r#"
+local state = redis.call('HMGET', KEYS[1], 'channel_id', 'session_id', 'token', 'endpoint')
+if not (state[1] and state[2] and state[3] and state[4]) then
+    return 0
+end
+local info = '{"channel_id":' .. state[1]
+    .. ',"endpoint":' .. cjson.encode(state[4])
+    .. ',"guild_id":' .. ARGV[1]
+    .. ',"session_id":' .. cjson.encode(state[2])
+    .. ',"token":' .. cjson.encode(state[3])
+    .. ',"user_id":' .. ARGV[2] .. '}'
+redis.call('PUBLISH', ARGV[3], info)
+return 1
+"#;
+
+/// Voice connections.
+///
+/// An audio node needs a voice state's session ID along with the token and
+/// endpoint from the voice server update before it can connect, and the two
+/// events can arrive in either order. Once both have been cached, the
+/// connection info is published so that audio nodes don't need to poll.
+impl Cache {
+    /// Returns a user's voice connection info for a guild, if their session
+    /// ID, token, and endpoint are all cached.
+    pub async fn get_voice_connection(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+    ) -> Result<Option<VoiceConnectionInfo>> {
+        let state = await!(self.get_voice_state(guild_id, user_id))?;
+
+        Ok(state.and_then(|state| state.connection_info(guild_id, user_id)))
+    }
+
+    /// Subscribes to voice connection info as it becomes complete, for all
+    /// guilds.
+    ///
+    /// Info is published when a voice server update completes it. A voice
+    /// state update with a new session ID clears the old session's token and
+    /// endpoint, so the new session's info isn't published until the next
+    /// voice server update. Info published while not subscribed is missed, so
+    /// subscribe before joining voice channels, or check with
+    /// [`get_voice_connection`] afterwards.
+    ///
+    /// This needs a Pub/Sub connection, since a paired connection can't
+    /// subscribe.
+    ///
+    /// [`get_voice_connection`]: #method.get_voice_connection
+    pub async fn subscribe_voice_connections(
+        pubsub: &PubsubConnection,
+    ) -> Result<impl Stream<Item = Result<VoiceConnectionInfo>>> {
+        let messages = await!(pubsub.subscribe(&gen::voice_connections()).compat())?;
+
+        Ok(messages.compat().map(|message| match message? {
+            RespValue::BulkString(bytes) => serde_json::from_slice(&bytes).map_err(From::from),
+            _ => Err(Error::None),
+        }))
+    }
+
+    /// Publishes a user's voice connection info if it's complete.
+    pub(crate) fn publish_voice_connection(&self, guild_id: u64, user_id: u64) {
+        self.inner().eval_sync(
+            PUBLISH_IF_READY_SCRIPT,
+            vec![gen::user_voice_state(guild_id, user_id)],
+            vec![
+                RespValue::from(guild_id.to_string()),
+                RespValue::from(user_id.to_string()),
+                RespValue::from(gen::voice_connections()),
+            ],
+        );
+    }
+}
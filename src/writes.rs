@@ -0,0 +1,348 @@
+//! Tracking of fire-and-forget writes.
+//!
+//! Writes such as [`CommandablePairedConnection::hmset_sync`] don't wait for
+//! their replies, so that callers handling gateway events aren't held up on
+//! Redis. Their replies are instead waited on in order by a task on the Tokio
+//! executor, which logs failures and passes them to the configured
+//! [`WriteErrorHandler`]. The task is spawned by the first tracked write or
+//! flush, so connections can be created outside of a runtime. Since Redis replies to
+//! commands in the order they were sent, waiting on them in order costs
+//! nothing extra.
+//!
+//! [`CommandablePairedConnection::hmset_sync`]: ../commands/struct.CommandablePairedConnection.html#method.hmset_sync
+//! [`WriteErrorHandler`]: struct.WriteErrorHandler.html
+
+use crate::error::{Error, Result};
+use futures::{
+    channel::{mpsc, oneshot},
+    future::{FutureExt as _, TryFutureExt as _},
+    stream::StreamExt as _,
+};
+use redis_async::{
+    error::Error as RedisError,
+    resp::RespValue,
+};
+use std::{
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    future::Future,
+    io::ErrorKind,
+    pin::Pin,
+    result::Result as StdResult,
+    sync::{Arc, Mutex},
+};
+use tokio_executor::{DefaultExecutor, Executor};
+
+/// The reply to a write, as waited on by the tracking task.
+pub(crate) type Reply = Pin<Box<dyn Future<Output = StdResult<RespValue, RedisError>> + Send>>;
+
+type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A fire-and-forget write that failed.
+#[derive(Clone, Debug)]
+pub struct WriteError {
+    /// The name of the command, such as `HMSET`.
+    pub command: String,
+    /// Why the write failed.
+    pub error: Arc<Error>,
+}
+
+impl Display for WriteError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{} failed: {}", self.command, self.error)
+    }
+}
+
+/// Receives the failures of fire-and-forget writes.
+///
+/// Failures are always logged; a handler is for reacting to them, such as by
+/// counting them or resyncing the affected guild.
+#[derive(Clone)]
+pub struct WriteErrorHandler(Arc<dyn Fn(WriteError) + Send + Sync>);
+
+impl WriteErrorHandler {
+    /// Creates a handler that calls a function with each failure.
+    ///
+    /// The function is called from the task that waits on replies, so later
+    /// failures aren't reported until it returns.
+    pub fn callback(f: impl Fn(WriteError) + Send + Sync + 'static) -> Self {
+        WriteErrorHandler(Arc::new(f))
+    }
+
+    /// Creates a handler that sends each failure over a channel, along with
+    /// the receiving end of the channel.
+    ///
+    /// Failures are dropped once the receiver is dropped.
+    pub fn channel() -> (Self, mpsc::UnboundedReceiver<WriteError>) {
+        let (tx, rx) = mpsc::unbounded();
+
+        let handler = Self::callback(move |error| {
+            let _ = tx.unbounded_send(error);
+        });
+
+        (handler, rx)
+    }
+
+    fn call(&self, error: WriteError) {
+        (self.0)(error)
+    }
+}
+
+impl Debug for WriteErrorHandler {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str("WriteErrorHandler")
+    }
+}
+
+enum Message {
+    Write(String, Reply),
+    /// Sends back the first failure since the last flush, once all previous
+    /// writes have been replied to.
+    Flush(oneshot::Sender<Option<WriteError>>),
+}
+
+/// Waits on the replies to fire-and-forget writes in a task.
+///
+/// Clones share the same task, which stops once all clones are dropped.
+#[derive(Clone)]
+pub(crate) struct WriteTracker {
+    sender: mpsc::UnboundedSender<Message>,
+    /// The task that waits on replies, until it's spawned.
+    task: Arc<Mutex<Option<Task>>>,
+}
+
+impl WriteTracker {
+    /// Creates a tracker whose task is spawned onto the current Tokio executor
+    /// by the first tracked write or flush.
+    pub fn new(handler: Option<WriteErrorHandler>) -> Self {
+        let (tracker, task) = Self::with_task(handler);
+        *tracker.task.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(task.boxed());
+
+        tracker
+    }
+
+    /// Creates a tracker along with the task that waits on its replies, for
+    /// the caller to run.
+    fn with_task(
+        handler: Option<WriteErrorHandler>,
+    ) -> (Self, impl Future<Output = ()> + Send) {
+        let (tx, rx) = mpsc::unbounded();
+        let tracker = Self {
+            sender: tx,
+            task: Arc::new(Mutex::new(None)),
+        };
+
+        (tracker, run(rx, handler))
+    }
+
+    /// Spawns the task onto the current Tokio executor, if it hasn't been
+    /// already.
+    ///
+    /// Fails if called outside of a Tokio runtime, or if its executor is
+    /// shutting down, in which case a later call can try again.
+    fn start(&self) -> Result<()> {
+        self.start_on(&mut DefaultExecutor::current())
+    }
+
+    /// Spawns the task onto the given executor, if it hasn't been already.
+    pub fn start_on(&self, executor: &mut impl Executor) -> Result<()> {
+        let mut slot = self.task.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let task = match slot.take() {
+            Some(task) => task,
+            None => return Ok(()),
+        };
+
+        if let Err(why) = executor.status() {
+            *slot = Some(task);
+
+            return Err(Error::from(why));
+        }
+
+        let task = async move {
+            await!(task);
+
+            Ok::<(), ()>(())
+        };
+
+        executor.spawn(Box::new(task.boxed().compat()))?;
+
+        Ok(())
+    }
+
+    /// Waits on the reply to a write in the background.
+    pub fn track(&self, command: String, reply: Reply) {
+        // If the task can't be started, the write is still queued to be waited
+        // on once a later write or flush starts it.
+        if let Err(why) = self.start() {
+            warn!("Couldn't start the write tracker: {:?}", why);
+        }
+
+        if self.sender.unbounded_send(Message::Write(command, reply)).is_err() {
+            warn!("Write tracker stopped; not waiting on a reply");
+        }
+    }
+
+    /// Waits for the replies to all writes tracked so far, returning the first
+    /// failure since the last flush.
+    ///
+    /// Fails with [`Error::Spawn`] if the task waiting on replies hasn't been
+    /// started and can't be, such as outside of a Tokio runtime.
+    ///
+    /// [`Error::Spawn`]: ../enum.Error.html#variant.Spawn
+    pub async fn flush(&self) -> Result<()> {
+        self.start()?;
+
+        let (tx, rx) = oneshot::channel();
+
+        if self.sender.unbounded_send(Message::Flush(tx)).is_err() {
+            return Err(Error::WriteTrackerStopped);
+        }
+
+        match await!(rx) {
+            Ok(None) => Ok(()),
+            Ok(Some(why)) => Err(Error::Write(why)),
+            Err(_) => Err(Error::WriteTrackerStopped),
+        }
+    }
+}
+
+async fn run(
+    mut rx: mpsc::UnboundedReceiver<Message>,
+    handler: Option<WriteErrorHandler>,
+) {
+    let mut first_error = None;
+
+    while let Some(message) = await!(rx.next()) {
+        match message {
+            Message::Write(command, reply) => {
+                let why = match await!(reply) {
+                    Ok(_) => continue,
+                    Err(why) => why,
+                };
+
+                // As with other commands, a timed out error can only be from
+                // the timer.
+                let why = match why {
+                    RedisError::IO(ref why) if why.kind() == ErrorKind::TimedOut => {
+                        Error::Timeout
+                    },
+                    why => Error::from(why),
+                };
+
+                warn!("Fire-and-forget {} failed: {:?}", command, why);
+
+                let error = WriteError {
+                    command,
+                    error: Arc::new(why),
+                };
+
+                if first_error.is_none() {
+                    first_error = Some(error.clone());
+                }
+
+                if let Some(handler) = handler.as_ref() {
+                    handler.call(error);
+                }
+            },
+            Message::Flush(tx) => {
+                let _ = tx.send(first_error.take());
+            },
+        }
+    }
+}
+
+/// The name of a command, for reporting its failure.
+pub(crate) fn command_name(value: &RespValue) -> String {
+    match value {
+        RespValue::Array(values) => match values.first() {
+            Some(RespValue::BulkString(name)) => String::from_utf8_lossy(name).into_owned(),
+            _ => "command".to_owned(),
+        },
+        _ => "command".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use futures::{executor, future, stream::StreamExt};
+    use redis_async::{error::Error as RedisError, resp::RespValue};
+    use super::{command_name, WriteErrorHandler, WriteTracker};
+
+    fn failure(message: &str) -> super::Reply {
+        Box::pin(future::ready(Err(RedisError::Remote(message.to_owned()))))
+    }
+
+    fn success() -> super::Reply {
+        Box::pin(future::ready(Ok(RespValue::SimpleString("OK".to_owned()))))
+    }
+
+    #[test]
+    fn test_command_name() {
+        assert_eq!(command_name(&resp_array!["HMSET", "g:1", "name", "a"]), "HMSET");
+        assert_eq!(command_name(&RespValue::Nil), "command");
+    }
+
+    #[test]
+    fn test_flush_returns_first_error() {
+        let (tracker, task) = WriteTracker::with_task(None);
+
+        let flushes = async move {
+            tracker.track("SADD".to_owned(), success());
+            tracker.track("HMSET".to_owned(), failure("WRONGTYPE"));
+            tracker.track("DEL".to_owned(), failure("other"));
+
+            match await!(tracker.flush()) {
+                Err(Error::Write(why)) => assert_eq!(why.command, "HMSET"),
+                other => panic!("Expected a write error: {:?}", other),
+            }
+
+            // Errors are only reported by the first flush after them.
+            assert!(await!(tracker.flush()).is_ok());
+        };
+
+        executor::block_on(future::join(task, flushes));
+    }
+
+    #[test]
+    fn test_handler_receives_every_error() {
+        let (handler, rx) = WriteErrorHandler::channel();
+        let (tracker, task) = WriteTracker::with_task(Some(handler));
+
+        let flush = async move {
+            tracker.track("HMSET".to_owned(), failure("one"));
+            tracker.track("SREM".to_owned(), success());
+            tracker.track("RPUSH".to_owned(), failure("two"));
+            assert!(await!(tracker.flush()).is_err());
+        };
+        executor::block_on(future::join(task, flush));
+
+        let commands = executor::block_on(rx.map(|why| why.command).collect::<Vec<_>>());
+        assert_eq!(commands, vec!["HMSET", "RPUSH"]);
+    }
+
+    #[test]
+    fn test_flush_outside_runtime() {
+        let tracker = WriteTracker::new(None);
+        tracker.track("SADD".to_owned(), success());
+
+        match executor::block_on(tracker.flush()) {
+            Err(Error::Spawn(_)) => {},
+            other => panic!("Expected the task to not be spawned: {:?}", other),
+        }
+
+        // The task is kept to be spawned later.
+        assert!(tracker.task.lock().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_flush_after_stop() {
+        let (tracker, task) = WriteTracker::with_task(None);
+        drop(task);
+
+        match executor::block_on(tracker.flush()) {
+            Err(Error::WriteTrackerStopped) => {},
+            other => panic!("Expected the tracker to be stopped: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use dabbot_cache::{
+    blocking::Cache,
+    Error,
+    test_util::FakeRedis,
+};
+use serenity::model::prelude::*;
+
+fn client() -> (FakeRedis, Cache) {
+    let server = FakeRedis::start().unwrap();
+    let cache = Cache::connect(&server.addr()).unwrap();
+
+    (server, cache)
+}
+
+#[test]
+fn choices() {
+    let (_server, cache) = client();
+
+    cache.push_choices(1, vec!["a".to_owned(), "b".to_owned()]).unwrap();
+    assert_eq!(cache.get_choices_ranged(1, 0, -1).unwrap(), vec!["b", "a"]);
+
+    cache.delete_choices(1).unwrap();
+    assert!(cache.get_choices(1).unwrap().is_empty());
+}
+
+#[test]
+fn fire_and_forget_then_read() {
+    let (_server, cache) = client();
+
+    cache.upsert_user(&User {
+        id: UserId(5),
+        avatar: None,
+        bot: true,
+        discriminator: 1,
+        name: "dab".to_owned(),
+    });
+
+    let user = cache.get_user(5).unwrap().unwrap();
+    assert!(user.bot);
+    assert_eq!(user.name, "dab");
+}
+
+#[test]
+fn missing_guild() {
+    let (_server, cache) = client();
+
+    assert!(cache.get_guild(1).is_err());
+}
+
+#[test]
+fn flush_reports_failed_write() {
+    let (_server, cache) = client();
+
+    cache.inner().rpush_sync("list".to_owned(), vec![1usize]);
+    cache.inner().sadd_sync("list".to_owned(), vec![2usize]);
+
+    match cache.flush() {
+        Err(Error::Write(why)) => assert_eq!(why.command, "SADD"),
+        other => panic!("Expected a write error: {:?}", other),
+    }
+
+    assert!(cache.flush().is_ok());
+}
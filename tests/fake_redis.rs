@@ -0,0 +1,936 @@
+#![feature(async_await, await_macro, futures_api)]
+
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
+use dabbot_cache::{
+    model::{HistoryEntry, LavalinkNode, Message as CachedMessage, VoiceConnectionInfo},
+    test_util::{FakeRedis, Value},
+    Cache,
+    Config,
+    Error,
+    RetryPolicy,
+};
+use futures::{
+    compat::Future01CompatExt,
+    future::{FutureExt, TryFutureExt},
+    stream::StreamExt,
+};
+use redis_async::client;
+use serenity::model::prelude::*;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    error::Error as StdError,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+use tokio;
+
+fn now() -> DateTime<FixedOffset> {
+    DateTime::from_utc(
+        NaiveDateTime::from_timestamp(1, 0),
+        FixedOffset::east(0),
+    )
+}
+
+fn panic(err: Box<StdError + 'static>) -> () {
+    panic!("err: {:?}", err);
+}
+
+async fn client() -> Result<(FakeRedis, Cache), Box<StdError + 'static>> {
+    await!(client_with_config(Config::default()))
+}
+
+async fn client_with_config(
+    config: Config,
+) -> Result<(FakeRedis, Cache), Box<StdError + 'static>> {
+    let server = FakeRedis::start()?;
+
+    let client = await!(client::paired_connect(&server.addr()).compat())?;
+
+    Ok((server, Cache::with_config(Arc::new(client), config)))
+}
+
+/// A configuration with a short timeout and a single retry, which is sent
+/// after a stalled reply of up to 300 milliseconds has arrived.
+fn retry_config(max_retries: u32) -> Config {
+    Config {
+        command_timeout: Some(Duration::from_millis(200)),
+        retry: RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::from_millis(150),
+            max_backoff: Duration::from_millis(150),
+        },
+        ..Config::default()
+    }
+}
+
+fn guild() -> Guild {
+    Guild {
+        afk_channel_id: Some(ChannelId(2)),
+        afk_timeout: 900,
+        application_id: None,
+        channels: {
+            let mut map = HashMap::new();
+
+            map.insert(ChannelId(4), GuildChannel {
+                id: ChannelId(4),
+                bitrate: Some(86400),
+                category_id: None,
+                guild_id: GuildId(1),
+                kind: ChannelType::Voice,
+                last_message_id: None,
+                last_pin_timestamp: None,
+                name: "some-channel".to_owned(),
+                permission_overwrites: vec![],
+                position: 2,
+                topic: None,
+                user_limit: None,
+                nsfw: false,
+            });
+
+            map
+        },
+        default_message_notifications: DefaultMessageNotificationLevel::Mentions,
+        emojis: HashMap::new(),
+        explicit_content_filter: ExplicitContentFilter::None,
+        features: vec![],
+        icon: None,
+        id: GuildId(1),
+        joined_at: now(),
+        large: false,
+        member_count: 1,
+        members: {
+            let mut map = HashMap::new();
+
+            map.insert(UserId(5), Member {
+                deaf: false,
+                guild_id: GuildId(1),
+                joined_at: None,
+                mute: false,
+                nick: Some("dab".to_owned()),
+                roles: vec![RoleId(6)],
+                user: User {
+                    id: UserId(5),
+                    avatar: None,
+                    bot: false,
+                    discriminator: 1,
+                    name: "hello".to_owned(),
+                },
+            });
+
+            map
+        },
+        mfa_level: MfaLevel::Elevated,
+        name: "a guild".to_owned(),
+        owner_id: UserId(5),
+        presences: HashMap::new(),
+        region: "us-west".to_owned(),
+        roles: {
+            let mut map = HashMap::new();
+
+            map.insert(RoleId(6), Role {
+                id: RoleId(6),
+                colour: 1u64.into(),
+                hoist: true,
+                managed: false,
+                mentionable: true,
+                name: "a role".to_owned(),
+                permissions: Permissions::MOVE_MEMBERS,
+                position: 1,
+            });
+
+            map
+        },
+        splash: None,
+        system_channel_id: None,
+        verification_level: VerificationLevel::High,
+        voice_states: {
+            let mut map = HashMap::new();
+
+            map.insert(UserId(5), VoiceState {
+                channel_id: Some(ChannelId(4)),
+                deaf: false,
+                mute: false,
+                self_deaf: false,
+                self_mute: false,
+                session_id: "a string".to_owned(),
+                suppress: false,
+                token: None,
+                user_id: UserId(5),
+            });
+
+            map
+        },
+    }
+}
+
+#[test]
+fn guild_round_trip() {
+    async fn _guild_round_trip() -> Result<(), Box<StdError + 'static>> {
+        let (server, client) = await!(client())?;
+
+        await!(client.upsert_guild(&guild()))?;
+        await!(client.flush())?;
+
+        let guild = await!(client.get_guild(1))?;
+        assert_eq!(guild.name, "a guild");
+        assert_eq!(guild.owner_id, 5);
+        assert_eq!(guild.afk_channel_id, Some(2));
+        assert_eq!(guild.channels, {
+            let mut set = HashSet::with_capacity(1);
+            set.insert(4);
+            set
+        });
+        assert!(guild.members.contains(&5));
+        assert!(guild.roles.contains(&6));
+
+        let member = await!(client.get_member(1, 5))?.unwrap();
+        assert_eq!(member.nick.as_ref().map(AsRef::as_ref), Some("dab"));
+        assert_eq!(member.roles, vec![6]);
+        assert_eq!(member.user.name, "hello");
+
+        let roles = await!(client.get_roles(1))?;
+        assert_eq!(roles[&6].name, "a role");
+
+        let states = await!(client.get_voice_states(1))?;
+        assert_eq!(states[&5].channel_id, 4);
+        assert_eq!(await!(client.get_channel_voice_states(4))?, vec![5]);
+        assert_eq!(await!(client.channel_listener_count(4))?, 1);
+
+        assert!(await!(client.verify_guild(1))?.is_empty());
+
+        client.delete_guild(1);
+        assert!(await!(client.get_guild(1)).is_err());
+        assert!(server.value("g:1").is_none());
+
+        Ok(())
+    }
+
+    tokio::run(_guild_round_trip().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn member_search() {
+    async fn _member_search() -> Result<(), Box<StdError + 'static>> {
+        let (_server, client) = await!(client())?;
+        let guild = guild();
+
+        await!(client.upsert_guild(&guild))?;
+
+        let mut member = guild.members[&UserId(5)].clone();
+        member.nick = None;
+        member.user.id = UserId(7);
+        member.user.name = "Hellen".to_owned();
+        member.user.discriminator = 42;
+        client.upsert_member(&member)?;
+
+        assert_eq!(await!(client.search_members(1, "HEL", 10))?, vec![7, 5]);
+        assert_eq!(await!(client.search_members(1, "hel", 1))?, vec![7]);
+        assert_eq!(await!(client.search_members(1, "da", 10))?, vec![5]);
+        assert!(await!(client.search_members(1, "x", 10))?.is_empty());
+
+        assert_eq!(await!(client.resolve_member_tag(1, "Hello#0001"))?, Some(5));
+        assert_eq!(await!(client.resolve_member_tag(1, "hellen#0042"))?, Some(7));
+        assert_eq!(await!(client.resolve_member_tag(1, "hello#0002"))?, None);
+        assert_eq!(await!(client.resolve_member_tag(1, "hello"))?, None);
+
+        // Reindexing replaces the old nickname.
+        let mut member = guild.members[&UserId(5)].clone();
+        member.nick = None;
+        client.upsert_member(&member)?;
+        assert!(await!(client.search_members(1, "da", 10))?.is_empty());
+
+        client.delete_member(1, 7);
+        assert_eq!(await!(client.search_members(1, "hel", 10))?, vec![5]);
+
+        await!(client.flush())?;
+
+        Ok(())
+    }
+
+    tokio::run(_member_search().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn stale_presences() {
+    async fn _stale_presences() -> Result<(), Box<StdError + 'static>> {
+        let (server, client) = await!(client())?;
+        let mut guild = guild();
+        let presence: Presence = serde_json::from_str(r#"{
+            "game": {"name": "a game", "type": 0},
+            "status": "online",
+            "user": {"id": "5"}
+        }"#)?;
+        guild.presences.insert(UserId(5), presence);
+
+        await!(client.upsert_guild(&guild))?;
+        assert_eq!(await!(client.get_presence(1, 5))?.unwrap().status, "online");
+
+        // The member went offline while the guild was unavailable, so they're
+        // left out of the next GuildCreate.
+        guild.presences.clear();
+        await!(client.upsert_guild(&guild))?;
+        await!(client.flush())?;
+
+        assert!(await!(client.get_presence(1, 5))?.is_none());
+        assert!(await!(client.get_guild_presences(1))?.is_empty());
+        assert!(server.value("g:1:p:5").is_none());
+
+        Ok(())
+    }
+
+    tokio::run(_stale_presences().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn repair_dangling_member() {
+    async fn _repair_dangling_member() -> Result<(), Box<StdError + 'static>> {
+        let (server, client) = await!(client())?;
+
+        await!(client.upsert_guild(&guild()))?;
+        await!(client.inner().del("g:1:m:5".to_owned()))?;
+
+        let report = await!(client.verify_guild(1))?;
+        assert_eq!(report.dangling_members, vec![5]);
+
+        await!(client.repair_guild(1))?;
+        await!(client.flush())?;
+        assert!(await!(client.verify_guild(1))?.is_empty());
+        assert!(server.value("g:1:m").is_none());
+        assert!(server.value("g:1:m:5:r").is_none());
+        assert!(server.value("g:1:m:5:s").is_none());
+        assert!(server.value("g:1:ms").is_none());
+        assert!(await!(client.search_members(1, "hel", 10))?.is_empty());
+
+        Ok(())
+    }
+
+    tokio::run(_repair_dangling_member().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn member_without_user() {
+    async fn _member_without_user() -> Result<(), Box<StdError + 'static>> {
+        let (_server, client) = await!(client())?;
+
+        await!(client.upsert_guild(&guild()))?;
+        await!(client.inner().del("u:5".to_owned()))?;
+
+        assert!(await!(client.get_member(1, 5))?.is_none());
+        assert!(await!(client.get_members(1, vec![5]))?.is_empty());
+
+        Ok(())
+    }
+
+    tokio::run(_member_without_user().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn stale_listeners() {
+    async fn _stale_listeners() -> Result<(), Box<StdError + 'static>> {
+        let (server, client) = await!(client())?;
+        let mut guild = guild();
+
+        await!(client.upsert_guild(&guild))?;
+        assert_eq!(await!(client.channel_listener_count(4))?, 1);
+
+        // The channel was deleted while the guild was unavailable.
+        guild.channels.clear();
+        guild.voice_states.clear();
+        await!(client.upsert_guild(&guild))?;
+        await!(client.flush())?;
+
+        assert!(server.value("ch:4:l").is_none());
+        assert!(server.value("ch:4:v").is_none());
+
+        Ok(())
+    }
+
+    tokio::run(_stale_listeners().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn unknown_user_not_listening() {
+    async fn _unknown_user_not_listening() -> Result<(), Box<StdError + 'static>> {
+        let (server, client) = await!(client())?;
+        let guild = guild();
+
+        await!(client.upsert_guild(&guild))?;
+
+        let mut state = guild.voice_states[&UserId(5)].clone();
+        state.user_id = UserId(9);
+        await!(client.upsert_voice_state(1, &state))?;
+
+        let mut states = await!(client.get_channel_voice_states(4))?;
+        states.sort();
+        assert_eq!(states, vec![5, 9]);
+        assert_eq!(await!(client.channel_listeners(4))?, vec![5]);
+
+        // They're counted once they're cached and turn out not to be a bot.
+        let mut user = guild.members[&UserId(5)].user.clone();
+        user.id = UserId(9);
+        client.upsert_user(&user);
+        await!(client.flush())?;
+
+        let mut listeners = await!(client.channel_listeners(4))?;
+        listeners.sort();
+        assert_eq!(listeners, vec![5, 9]);
+
+        user.bot = true;
+        client.upsert_user(&user);
+        await!(client.flush())?;
+        assert_eq!(await!(client.channel_listeners(4))?, vec![5]);
+
+        // Leaving the channel drops the guild from the user's voice guilds.
+        state.channel_id = None;
+        await!(client.upsert_voice_state(1, &state))?;
+        await!(client.flush())?;
+        assert!(server.value("u:9:v").is_none());
+
+        Ok(())
+    }
+
+    tokio::run(_unknown_user_not_listening().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn iter_guild_ids() {
+    async fn _iter_guild_ids() -> Result<(), Box<StdError + 'static>> {
+        let (_server, client) = await!(client())?;
+
+        await!(client.upsert_guild(&guild()))?;
+
+        let ids = await!(client.iter_guild_ids().collect::<Vec<_>>());
+        assert_eq!(ids.into_iter().collect::<Result<Vec<_>, _>>()?, vec![1]);
+
+        client.delete_guild(1);
+        assert!(await!(client.iter_guild_ids().collect::<Vec<_>>()).is_empty());
+
+        Ok(())
+    }
+
+    tokio::run(_iter_guild_ids().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn index_guild_ids() {
+    async fn _index_guild_ids() -> Result<(), Box<StdError + 'static>> {
+        let (_server, client) = await!(client())?;
+
+        // A guild cached before guilds were indexed.
+        await!(client.upsert_guild(&guild()))?;
+        await!(client.flush())?;
+        await!(client.inner().srem("guilds".to_owned(), vec![1]))?;
+        assert!(await!(client.iter_guild_ids().collect::<Vec<_>>()).is_empty());
+
+        assert_eq!(await!(client.index_guild_ids())?, 1);
+
+        let ids = await!(client.iter_guild_ids().collect::<Vec<_>>());
+        assert_eq!(ids.into_iter().collect::<Result<Vec<_>, _>>()?, vec![1]);
+
+        Ok(())
+    }
+
+    tokio::run(_index_guild_ids().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn choices() {
+    async fn _choices() -> Result<(), Box<StdError + 'static>> {
+        let (_server, client) = await!(client())?;
+
+        await!(client.push_choices(1, vec!["a".to_owned(), "b".to_owned()]))?;
+        assert_eq!(await!(client.get_choices_ranged(1, 0, -1))?, vec!["b", "a"]);
+        assert_eq!(await!(client.get_choices_ranged(1, 0, 0))?, vec!["b"]);
+
+        await!(client.delete_choices(1))?;
+        assert!(await!(client.get_choices_ranged(1, 0, -1))?.is_empty());
+
+        Ok(())
+    }
+
+    tokio::run(_choices().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn voice_connection() {
+    async fn _voice_connection() -> Result<(), Box<StdError + 'static>> {
+        let (server, client) = await!(client())?;
+        let guild = guild();
+
+        await!(client.upsert_guild(&guild))?;
+        assert!(await!(client.get_voice_connection(1, 5))?.is_none());
+
+        client.upsert_voice_state_info(1, 5, "endpoint".to_owned(), "token".to_owned());
+
+        // A later voice state update without a token keeps the cached one.
+        await!(client.upsert_voice_state(1, &guild.voice_states[&UserId(5)]))?;
+
+        let info = await!(client.get_voice_connection(1, 5))?.unwrap();
+        assert_eq!(info.channel_id, 4);
+        assert_eq!(info.endpoint, "endpoint");
+        assert_eq!(info.session_id, "a string");
+        assert_eq!(info.token, "token");
+
+        // Only the voice server update completed the info.
+        await!(client.flush())?;
+        let published = server.published("vc");
+        assert_eq!(published.len(), 1);
+        assert_eq!(serde_json::from_slice::<VoiceConnectionInfo>(&published[0])?, info);
+
+        Ok(())
+    }
+
+    tokio::run(_voice_connection().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn lavalink_nodes() {
+    async fn _lavalink_nodes() -> Result<(), Box<StdError + 'static>> {
+        let (_server, client) = await!(client())?;
+
+        await!(client.record_lavalink_heartbeat("10.0.0.1:2333", "us", 4, 0.5))?;
+        await!(client.record_lavalink_heartbeat("10.0.0.2:2333", "eu", 1, 0.25))?;
+
+        let mut nodes = await!(client.get_lavalink_nodes())?;
+        nodes.sort_by(|a, b| a.address.cmp(&b.address));
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0].players, 4);
+        assert_eq!(nodes[1].region, "eu");
+        // The heartbeat is stamped with the time in Redis.
+        assert!(nodes.iter().all(|node| node.last_heartbeat > 0));
+
+        await!(client.remove_lavalink_node("10.0.0.1:2333"))?;
+        assert_eq!(await!(client.get_lavalink_nodes())?.len(), 1);
+        assert!(await!(client.get_guild_lavalink_node(1))?.is_none());
+
+        Ok(())
+    }
+
+    tokio::run(_lavalink_nodes().map_err(panic).boxed().compat());
+}
+
+/// Records a node whose last heartbeat was long ago.
+async fn record_stale_node<'a>(
+    client: &'a Cache,
+    address: &'a str,
+) -> Result<(), Box<StdError + 'static>> {
+    let node = LavalinkNode {
+        address: address.to_owned(),
+        cpu_load: 0.0,
+        last_heartbeat: 0,
+        players: 0,
+        region: "us".to_owned(),
+    };
+
+    await!(client.inner().hmset("ll:n".to_owned(), vec![
+        address.to_owned(),
+        serde_json::to_string(&node)?,
+    ]))?;
+
+    Ok(())
+}
+
+#[test]
+fn lavalink_assign_least_loaded() {
+    async fn _lavalink_assign_least_loaded() -> Result<(), Box<StdError + 'static>> {
+        let (_server, client) = await!(client())?;
+
+        await!(client.record_lavalink_heartbeat("a:2333", "us", 4, 0.1))?;
+        await!(client.record_lavalink_heartbeat("b:2333", "us", 1, 0.9))?;
+        await!(client.record_lavalink_heartbeat("c:2333", "us", 1, 0.2))?;
+        await!(record_stale_node(&client, "d:2333"))?;
+
+        // Ties on players are broken by CPU load, and stale nodes are skipped
+        // even though they have the fewest players.
+        let assignment = await!(client.assign_lavalink_node(1, None))?.unwrap();
+        assert_eq!(assignment.node, "c:2333");
+        assert!(assignment.changed);
+        assert!(assignment.previous.is_none());
+
+        // The assignment counts as a player until the next heartbeat.
+        let assignment = await!(client.assign_lavalink_node(2, None))?.unwrap();
+        assert_eq!(assignment.node, "b:2333");
+        assert_eq!(await!(client.get_guild_lavalink_node(1))?, Some("c:2333".to_owned()));
+
+        Ok(())
+    }
+
+    tokio::run(_lavalink_assign_least_loaded().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn lavalink_assign_prefers_region() {
+    async fn _lavalink_assign_prefers_region() -> Result<(), Box<StdError + 'static>> {
+        let (_server, client) = await!(client())?;
+
+        await!(client.record_lavalink_heartbeat("a:2333", "us", 1, 0.1))?;
+        await!(client.record_lavalink_heartbeat("b:2333", "eu", 9, 0.9))?;
+
+        let assignment = await!(client.assign_lavalink_node(1, Some("eu")))?.unwrap();
+        assert_eq!(assignment.node, "b:2333");
+
+        // Without a node in the region, any live node is used.
+        let assignment = await!(client.assign_lavalink_node(2, Some("asia")))?.unwrap();
+        assert_eq!(assignment.node, "a:2333");
+
+        Ok(())
+    }
+
+    tokio::run(_lavalink_assign_prefers_region().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn lavalink_assign_sticky() {
+    async fn _lavalink_assign_sticky() -> Result<(), Box<StdError + 'static>> {
+        let (_server, client) = await!(client())?;
+
+        await!(client.record_lavalink_heartbeat("a:2333", "us", 1, 0.1))?;
+        await!(client.record_lavalink_heartbeat("b:2333", "us", 2, 0.1))?;
+        assert_eq!(await!(client.assign_lavalink_node(1, None))?.unwrap().node, "a:2333");
+
+        // The guild stays on its node while it's alive, even if it's no
+        // longer the least loaded.
+        await!(client.record_lavalink_heartbeat("a:2333", "us", 10, 0.9))?;
+
+        let assignment = await!(client.assign_lavalink_node(1, None))?.unwrap();
+        assert_eq!(assignment.node, "a:2333");
+        assert!(!assignment.changed);
+        assert!(assignment.previous.is_none());
+
+        // Unchanged assignments don't count as new players.
+        let nodes = await!(client.get_lavalink_nodes())?;
+        assert!(nodes.iter().any(|node| node.address == "a:2333" && node.players == 10));
+
+        Ok(())
+    }
+
+    tokio::run(_lavalink_assign_sticky().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn lavalink_assign_stale() {
+    async fn _lavalink_assign_stale() -> Result<(), Box<StdError + 'static>> {
+        let (_server, client) = await!(client())?;
+
+        await!(client.record_lavalink_heartbeat("a:2333", "us", 1, 0.1))?;
+        assert_eq!(await!(client.assign_lavalink_node(1, None))?.unwrap().node, "a:2333");
+
+        await!(record_stale_node(&client, "a:2333"))?;
+        assert!(await!(client.assign_lavalink_node(1, None))?.is_none());
+
+        await!(client.record_lavalink_heartbeat("b:2333", "us", 5, 0.5))?;
+
+        let assignment = await!(client.assign_lavalink_node(1, None))?.unwrap();
+        assert_eq!(assignment.node, "b:2333");
+        assert!(assignment.changed);
+        assert_eq!(assignment.previous, Some("a:2333".to_owned()));
+
+        Ok(())
+    }
+
+    tokio::run(_lavalink_assign_stale().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn skip_votes() {
+    async fn _skip_votes() -> Result<(), Box<StdError + 'static>> {
+        let (server, client) = await!(client())?;
+
+        assert!(await!(client.add_skip_vote(1, "a", 5))?.is_none());
+
+        await!(client.set_current_track(1, Some("a".to_owned())))?;
+        assert_eq!(await!(client.add_skip_vote(1, "a", 5))?, Some(true));
+        assert_eq!(await!(client.add_skip_vote(1, "a", 5))?, Some(false));
+        assert!(await!(client.add_skip_vote(1, "b", 6))?.is_none());
+
+        // Setting the same track keeps the votes.
+        await!(client.set_current_track(1, Some("a".to_owned())))?;
+        assert!(server.value("g:1:sv").is_some());
+
+        await!(client.set_current_track(1, Some("b".to_owned())))?;
+        assert!(server.value("g:1:sv").is_none());
+        assert_eq!(await!(client.get_current_track(1))?, Some("b".to_owned()));
+
+        await!(client.add_skip_vote(1, "b", 5))?;
+        await!(client.set_current_track(1, None))?;
+        assert!(server.value("g:1:sv").is_none());
+        assert!(await!(client.get_current_track(1))?.is_none());
+
+        Ok(())
+    }
+
+    tokio::run(_skip_votes().map_err(panic).boxed().compat());
+}
+
+fn history_entry(track: &str) -> HistoryEntry {
+    HistoryEntry {
+        played_at: 1540000000,
+        requester_id: 5,
+        track: track.to_owned(),
+    }
+}
+
+#[test]
+fn history() {
+    async fn _history() -> Result<(), Box<StdError + 'static>> {
+        let (server, client) = await!(client_with_config(Config {
+            history_size: 3,
+            ..Config::default()
+        }))?;
+
+        for track in &["a", "b", "c", "d"] {
+            client.push_history(1, &history_entry(track))?;
+        }
+        await!(client.flush())?;
+
+        // The oldest entry was trimmed off.
+        assert_eq!(await!(client.get_history_len(1))?, 3);
+        assert_eq!(await!(client.get_history(1, 0, 2))?, vec![
+            history_entry("d"),
+            history_entry("c"),
+        ]);
+        assert_eq!(await!(client.get_history(1, 1, 2))?, vec![history_entry("b")]);
+        assert!(await!(client.get_history(1, 2, 2))?.is_empty());
+        assert!(await!(client.get_history(1, 0, 0))?.is_empty());
+
+        assert_eq!(await!(client.requeue_from_history(1, 1))?, Some(history_entry("c")));
+        assert!(await!(client.requeue_from_history(1, 2))?.is_none());
+        assert_eq!(await!(client.get_history(1, 0, 3))?, vec![
+            history_entry("d"),
+            history_entry("b"),
+        ]);
+
+        let queue = server.value("queue:1");
+        assert_eq!(queue, Some(Value::List(VecDeque::from(vec![b"c".to_vec()]))));
+
+        await!(client.delete_history(1))?;
+        assert_eq!(await!(client.get_history_len(1))?, 0);
+
+        Ok(())
+    }
+
+    tokio::run(_history().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn ratelimit_fixed() {
+    async fn _ratelimit_fixed() -> Result<(), Box<StdError + 'static>> {
+        let (_server, client) = await!(client())?;
+        let window = Duration::from_secs(60);
+
+        let limit = await!(client.ratelimit_fixed(1, 5, "play", 2, window))?;
+        assert!(limit.allowed);
+        assert_eq!(limit.remaining, 1);
+        assert!(limit.reset_after <= window);
+        assert!(await!(client.ratelimit_fixed(1, 5, "play", 2, window))?.allowed);
+        assert!(!await!(client.ratelimit_fixed(1, 5, "play", 2, window))?.allowed);
+
+        // A counter that lost its expiry gets a new one on the next hit.
+        await!(client.inner().set("rl:f:1:5:skip".to_owned(), vec!["7".to_owned()]))?;
+        let limit = await!(client.ratelimit_fixed(1, 5, "skip", 2, window))?;
+        assert!(!limit.allowed);
+        assert!(limit.reset_after > Duration::from_secs(0));
+
+        await!(client.reset_ratelimit(1, 5, "play"))?;
+        assert!(await!(client.ratelimit_fixed(1, 5, "play", 2, window))?.allowed);
+
+        Ok(())
+    }
+
+    tokio::run(_ratelimit_fixed().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn ratelimit_sliding() {
+    async fn _ratelimit_sliding() -> Result<(), Box<StdError + 'static>> {
+        let (_server, client) = await!(client())?;
+        let window = Duration::from_millis(200);
+
+        assert!(await!(client.ratelimit_sliding(1, 5, "play", 2, window))?.allowed);
+        let limit = await!(client.ratelimit_sliding(1, 5, "play", 2, window))?;
+        assert!(limit.allowed);
+        assert_eq!(limit.remaining, 0);
+
+        let limit = await!(client.ratelimit_sliding(1, 5, "play", 2, window))?;
+        assert!(!limit.allowed);
+        assert!(limit.reset_after <= window);
+
+        // Both hits leave the window once it has passed.
+        thread::sleep(window * 2);
+        let limit = await!(client.ratelimit_sliding(1, 5, "play", 2, window))?;
+        assert!(limit.allowed);
+        assert_eq!(limit.remaining, 1);
+
+        Ok(())
+    }
+
+    tokio::run(_ratelimit_sliding().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn update_message() {
+    async fn _update_message() -> Result<(), Box<StdError + 'static>> {
+        let (_server, client) = await!(client())?;
+
+        // IDs and hashes above 2^53 would be rounded if re-encoded in Lua.
+        let message = |id| CachedMessage {
+            author_id: 5,
+            content_hash: CachedMessage::hash_content("old"),
+            id,
+            timestamp: 1,
+        };
+        let entries = vec![
+            serde_json::to_vec(&message(9_007_199_254_740_993))?,
+            serde_json::to_vec(&message(9_007_199_254_740_995))?,
+        ];
+        await!(client.inner().rpush("ch:1:m".to_owned(), entries))?;
+
+        let event: MessageUpdateEvent = serde_json::from_str(
+            r#"{"id":"9007199254740995","channel_id":"1","content":"new"}"#,
+        )?;
+        assert!(await!(client.update_message(&event))?);
+
+        let messages = await!(client.recent_messages(1, 10))?;
+        assert_eq!(messages[0], message(9_007_199_254_740_993));
+        assert_eq!(messages[1], CachedMessage {
+            content_hash: CachedMessage::hash_content("new"),
+            ..message(9_007_199_254_740_995)
+        });
+
+        let event: MessageUpdateEvent = serde_json::from_str(
+            r#"{"id":"2","channel_id":"1","content":"new"}"#,
+        )?;
+        assert!(!await!(client.update_message(&event))?);
+
+        assert!(await!(client.delete_message(1, 9_007_199_254_740_993))?);
+        assert!(!await!(client.delete_message(1, 9_007_199_254_740_993))?);
+        assert_eq!(await!(client.recent_messages(1, 10))?.len(), 1);
+
+        Ok(())
+    }
+
+    tokio::run(_update_message().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn delete_messages() {
+    async fn _delete_messages() -> Result<(), Box<StdError + 'static>> {
+        let (_server, client) = await!(client())?;
+
+        let message = |id| CachedMessage {
+            author_id: 5,
+            content_hash: CachedMessage::hash_content("a"),
+            id,
+            timestamp: 1,
+        };
+        let entries = vec![
+            serde_json::to_vec(&message(1))?,
+            serde_json::to_vec(&message(2))?,
+            serde_json::to_vec(&message(3))?,
+        ];
+        await!(client.inner().rpush("ch:1:m".to_owned(), entries))?;
+
+        assert_eq!(await!(client.delete_messages(1, vec![3, 1, 4]))?, 2);
+        assert_eq!(await!(client.delete_messages(1, vec![]))?, 0);
+        assert_eq!(await!(client.recent_messages(1, 10))?, vec![message(2)]);
+
+        Ok(())
+    }
+
+    tokio::run(_delete_messages().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn voice_connection_new_session() {
+    async fn _voice_connection_new_session() -> Result<(), Box<StdError + 'static>> {
+        let (server, client) = await!(client())?;
+        let guild = guild();
+
+        await!(client.upsert_guild(&guild))?;
+        client.upsert_voice_state_info(1, 5, "endpoint".to_owned(), "token".to_owned());
+
+        let mut state = guild.voice_states[&UserId(5)].clone();
+        state.session_id = "another string".to_owned();
+        await!(client.upsert_voice_state(1, &state))?;
+
+        // The old session's token and endpoint don't complete the new one.
+        assert!(await!(client.get_voice_connection(1, 5))?.is_none());
+        await!(client.flush())?;
+        assert_eq!(server.published("vc").len(), 1);
+
+        client.upsert_voice_state_info(1, 5, "endpoint 2".to_owned(), "token 2".to_owned());
+
+        let info = await!(client.get_voice_connection(1, 5))?.unwrap();
+        assert_eq!(info.endpoint, "endpoint 2");
+        assert_eq!(info.session_id, "another string");
+        assert_eq!(info.token, "token 2");
+
+        await!(client.flush())?;
+        let published = server.published("vc");
+        assert_eq!(published.len(), 2);
+        assert_eq!(serde_json::from_slice::<VoiceConnectionInfo>(&published[1])?, info);
+
+        Ok(())
+    }
+
+    tokio::run(_voice_connection_new_session().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn stalled_reply_times_out() {
+    async fn _stalled_reply_times_out() -> Result<(), Box<StdError + 'static>> {
+        let (server, client) = await!(client_with_config(retry_config(0)))?;
+        server.delay_replies("LRANGE", 1, Duration::from_millis(300));
+
+        match await!(client.get_choices_ranged(1, 0, -1)) {
+            Err(Error::Timeout) => {},
+            other => panic!("Expected a timeout: {:?}", other),
+        }
+
+        assert_eq!(server.received("LRANGE"), 1);
+
+        Ok(())
+    }
+
+    tokio::run(_stalled_reply_times_out().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn stalled_read_is_retried() {
+    async fn _stalled_read_is_retried() -> Result<(), Box<StdError + 'static>> {
+        let (server, client) = await!(client_with_config(retry_config(1)))?;
+        await!(client.push_choices(1, vec!["a".to_owned()]))?;
+        server.delay_replies("LRANGE", 1, Duration::from_millis(300));
+
+        assert_eq!(await!(client.get_choices_ranged(1, 0, -1))?, vec!["a".to_owned()]);
+        assert_eq!(server.received("LRANGE"), 2);
+
+        Ok(())
+    }
+
+    tokio::run(_stalled_read_is_retried().map_err(panic).boxed().compat());
+}
+
+#[test]
+fn stalled_write_is_not_retried() {
+    async fn _stalled_write_is_not_retried() -> Result<(), Box<StdError + 'static>> {
+        let (server, client) = await!(client_with_config(retry_config(1)))?;
+        server.delay_replies("LPUSH", 1, Duration::from_millis(300));
+
+        match await!(client.push_choices(1, vec!["a".to_owned()])) {
+            Err(Error::Timeout) => {},
+            other => panic!("Expected a timeout: {:?}", other),
+        }
+
+        assert_eq!(server.received("LPUSH"), 1);
+
+        // The push was applied once, even though its reply never arrived.
+        let client = client.with_timeout(None);
+        assert_eq!(await!(client.get_choices_ranged(1, 0, -1))?, vec!["a".to_owned()]);
+
+        Ok(())
+    }
+
+    tokio::run(_stalled_write_is_not_retried().map_err(panic).boxed().compat());
+}
@@ -0,0 +1,255 @@
+#![feature(async_await, await_macro, futures_api)]
+
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
+use dabbot_cache::{
+    test_util::FakeRedis,
+    Cache,
+};
+use futures::{
+    compat::Future01CompatExt,
+    future::{FutureExt, TryFutureExt},
+};
+use proptest::prelude::*;
+use redis_async::client;
+use serenity::model::prelude::*;
+use std::{
+    collections::HashMap,
+    error::Error as StdError,
+    sync::Arc,
+};
+use tokio::runtime::current_thread::Runtime;
+
+const GUILD_ID: u64 = 1;
+
+fn now() -> DateTime<FixedOffset> {
+    DateTime::from_utc(
+        NaiveDateTime::from_timestamp(1, 0),
+        FixedOffset::east(0),
+    )
+}
+
+/// Runs a round trip on a fresh fake server, panicking on errors.
+fn run<F>(f: impl FnOnce(Cache) -> F)
+    where F: std::future::Future<Output = Result<(), Box<StdError + 'static>>> + Send + 'static {
+    let server = FakeRedis::start().unwrap();
+    let mut runtime = Runtime::new().unwrap();
+
+    let connection = runtime.block_on(client::paired_connect(&server.addr())).unwrap();
+    let cache = Cache::new(Arc::new(connection));
+
+    runtime.block_on(f(cache).boxed().compat()).unwrap();
+}
+
+/// Generates names, including ones that look like numbers.
+fn name() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "[a-zA-Z][a-zA-Z0-9 _.-]{0,31}",
+        "[1-9][0-9]{0,15}",
+        "0[0-9]{1,31}",
+        "\\PC{1,32}",
+    ]
+}
+
+/// Generates IDs in the range of Discord snowflakes.
+fn snowflake() -> impl Strategy<Value = u64> {
+    1u64..(1 << 63)
+}
+
+prop_compose! {
+    fn role()(
+        id in snowflake(),
+        name in name(),
+        permissions in any::<u64>(),
+    ) -> Role {
+        Role {
+            id: RoleId(id),
+            colour: 1u64.into(),
+            hoist: false,
+            managed: false,
+            mentionable: false,
+            name,
+            permissions: Permissions::from_bits_truncate(permissions),
+            position: 1,
+        }
+    }
+}
+
+prop_compose! {
+    fn member()(
+        id in snowflake(),
+        name in name(),
+        discriminator in 0u16..10000,
+        bot in any::<bool>(),
+        nick in proptest::option::of(name()),
+        deaf in any::<bool>(),
+        mute in any::<bool>(),
+    ) -> Member {
+        Member {
+            deaf,
+            guild_id: GuildId(GUILD_ID),
+            joined_at: None,
+            mute,
+            nick,
+            roles: vec![],
+            user: User {
+                id: UserId(id),
+                avatar: None,
+                bot,
+                discriminator,
+                name,
+            },
+        }
+    }
+}
+
+prop_compose! {
+    fn voice_state()(
+        channel_id in snowflake(),
+        session_id in prop_oneof![
+            "[0-9a-f]{32}",
+            "[1-9][0-9]{0,17}",
+            "[1-9][0-9]{31}",
+            "0[0-9]{1,31}",
+        ],
+        deaf in any::<bool>(),
+        mute in any::<bool>(),
+        self_deaf in any::<bool>(),
+        self_mute in any::<bool>(),
+    ) -> VoiceState {
+        VoiceState {
+            channel_id: Some(ChannelId(channel_id)),
+            deaf,
+            mute,
+            self_deaf,
+            self_mute,
+            session_id,
+            suppress: false,
+            token: None,
+            user_id: UserId(0),
+        }
+    }
+}
+
+prop_compose! {
+    fn guild()(
+        role in role(),
+        mut member in member(),
+        mut voice_state in voice_state(),
+        name in name(),
+        region in name(),
+        afk_channel_id in proptest::option::of(snowflake()),
+    ) -> Guild {
+        let owner_id = member.user.id;
+        member.roles.push(role.id);
+        voice_state.user_id = member.user.id;
+
+        Guild {
+            afk_channel_id: afk_channel_id.map(ChannelId),
+            afk_timeout: 900,
+            application_id: None,
+            channels: HashMap::new(),
+            default_message_notifications: DefaultMessageNotificationLevel::Mentions,
+            emojis: HashMap::new(),
+            explicit_content_filter: ExplicitContentFilter::None,
+            features: vec![],
+            icon: None,
+            id: GuildId(GUILD_ID),
+            joined_at: now(),
+            large: false,
+            member_count: 1,
+            members: vec![(member.user.id, member)].into_iter().collect(),
+            mfa_level: MfaLevel::None,
+            name,
+            owner_id,
+            presences: HashMap::new(),
+            region,
+            roles: vec![(role.id, role)].into_iter().collect(),
+            splash: None,
+            system_channel_id: None,
+            verification_level: VerificationLevel::None,
+            voice_states: vec![(voice_state.user_id, voice_state)].into_iter().collect(),
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn guild_round_trip(guild in guild()) {
+        run(move |cache| async move {
+            await!(cache.upsert_guild(&guild))?;
+
+            let cached = await!(cache.get_guild(GUILD_ID))?;
+            assert_eq!(cached.name, guild.name);
+            assert_eq!(cached.owner_id, guild.owner_id.0);
+            assert_eq!(cached.region, guild.region);
+            assert_eq!(cached.afk_channel_id, guild.afk_channel_id.map(|id| id.0));
+            assert_eq!(cached.members.len(), 1);
+            assert_eq!(cached.roles.len(), 1);
+            assert_eq!(cached.voice_states.len(), 1);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn member_round_trip(guild in guild()) {
+        run(move |cache| async move {
+            await!(cache.upsert_guild(&guild))?;
+
+            for member in guild.members.values() {
+                let cached = await!(cache.get_member(GUILD_ID, member.user.id.0))?.unwrap();
+
+                assert_eq!(cached.deaf, member.deaf);
+                assert_eq!(cached.nick, member.nick);
+                assert_eq!(cached.roles, member.roles.iter().map(|id| id.0).collect::<Vec<_>>());
+                assert_eq!(cached.user.bot, member.user.bot);
+                assert_eq!(cached.user.discriminator, member.user.discriminator);
+                assert_eq!(cached.user.id, member.user.id.0);
+                assert_eq!(cached.user.name, member.user.name);
+            }
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn role_round_trip(guild in guild()) {
+        run(move |cache| async move {
+            await!(cache.upsert_guild(&guild))?;
+
+            let cached = await!(cache.get_roles(GUILD_ID))?;
+            assert_eq!(cached.len(), guild.roles.len());
+
+            for role in guild.roles.values() {
+                let cached = &cached[&role.id.0];
+
+                assert_eq!(cached.name, role.name);
+                assert_eq!(cached.permissions, role.permissions);
+            }
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn voice_state_round_trip(guild in guild()) {
+        run(move |cache| async move {
+            await!(cache.upsert_guild(&guild))?;
+
+            for state in guild.voice_states.values() {
+                let cached = await!(cache.get_voice_state(GUILD_ID, state.user_id.0))?.unwrap();
+
+                assert_eq!(Some(ChannelId(cached.channel_id)), state.channel_id);
+                assert_eq!(cached.deaf, state.deaf);
+                assert_eq!(cached.mute, state.mute);
+                assert_eq!(cached.self_deaf, state.self_deaf);
+                assert_eq!(cached.self_mute, state.self_mute);
+                assert_eq!(cached.session_id, state.session_id);
+            }
+
+            Ok(())
+        });
+    }
+}